@@ -0,0 +1,337 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::result::Result::Ok;
+
+use anyhow::*;
+use chrono::{Local, TimeZone};
+use flate2::read::MultiGzDecoder;
+use regex::Regex;
+
+use crate::dns::Resolution;
+use crate::geoip::GeoIp;
+use crate::input;
+use crate::jail::Jail;
+use crate::parsers::{self, Kind, Parsers};
+use crate::utils::{log, Args, ParsingStatus, TimeSource};
+
+const TOP_OFFENDERS: usize = 10;
+
+struct Event {
+    kind: Kind,
+    ip: IpAddr,
+    ts: u64,
+    instant: bool,
+    // the jail's when not set by a geoip policy
+    allowance: Option<u8>,
+}
+
+#[derive(Default)]
+struct Report {
+    lines: HashMap<Kind, usize>,
+    errors: HashMap<Kind, usize>,
+    offences: HashMap<Kind, HashMap<IpAddr, usize>>,
+    bans: Vec<(u64, Kind, IpAddr)>,
+}
+
+/// Rotated versions of a logfile, oldest first - e.g. auth.log.2.gz, auth.log.1, auth.log
+pub fn rotated_files(path: &Path) -> Result<Vec<PathBuf>> {
+    if path.as_os_str() == input::STDIN {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let dir = path
+        .parent()
+        .ok_or_else(|| anyhow!("no parent for {:?}", path))?;
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| anyhow!("invalid file name {:?}", path))?;
+    let re = Regex::new(&format!(r"^{}\.(\d+)(\.gz)?$", regex::escape(name)))?;
+
+    let mut rotated = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let idx = file_name
+            .to_str()
+            .and_then(|n| re.captures(n))
+            .and_then(|c| c.get(1))
+            .and_then(|i| i.as_str().parse::<u32>().ok());
+        if let Some(idx) = idx {
+            rotated.push((idx, entry.path()));
+        }
+    }
+
+    // highest index is the oldest
+    rotated.sort_by(|a, b| b.0.cmp(&a.0));
+    let mut files: Vec<PathBuf> = rotated.into_iter().map(|(_, p)| p).collect();
+    files.push(path.to_path_buf());
+    Ok(files)
+}
+
+pub fn open(path: &Path) -> Result<Box<dyn BufRead>> {
+    if path.as_os_str() == input::STDIN {
+        return Ok(Box::new(BufReader::new(std::io::stdin())));
+    }
+
+    let file = File::open(path).with_context(|| format!("cant open {:?}", path))?;
+    if path.extension().is_some_and(|e| e == "gz") {
+        return Ok(Box::new(BufReader::new(MultiGzDecoder::new(file))));
+    }
+    Ok(Box::new(BufReader::new(file)))
+}
+
+// runs offences through the jail in the order they happened
+fn replay(mut events: Vec<Event>, jail: &Jail, jailtime: u32) -> Result<Vec<(u64, Kind, IpAddr)>> {
+    events.sort_by_key(|e| e.ts);
+
+    let mut bans = vec![];
+    let mut jailed: HashMap<IpAddr, u64> = HashMap::new();
+    for e in events {
+        // a jailed ip wouldn't have been able to offend again
+        if jailed.get(&e.ip).is_some_and(|until| *until > e.ts) {
+            continue;
+        }
+
+        let banned = e.instant
+            || match e.allowance {
+                Some(allowance) => jail.sentence_with(e.ip, e.ts, allowance)?,
+                None => jail.sentence(e.ip, e.ts)?,
+            };
+        if banned {
+            bans.push((e.ts, e.kind, e.ip));
+            jailed.insert(e.ip, e.ts + jailtime as u64);
+        }
+    }
+
+    Ok(bans)
+}
+
+fn format_ts(ts: u64) -> String {
+    Local
+        .timestamp_opt(ts as i64, 0)
+        .single()
+        .map(|d| d.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|| ts.to_string())
+}
+
+impl Report {
+    fn print(&self, sources: &[(Kind, PathBuf)]) {
+        println!("\nbans ({}):", self.bans.len());
+        for (ts, kind, ip) in &self.bans {
+            println!("  {} - {} - {}", format_ts(*ts), kind, ip);
+        }
+
+        for (kind, _) in sources {
+            let lines = self.lines.get(kind).unwrap_or(&0);
+            let errors = self.errors.get(kind).unwrap_or(&0);
+            let empty = HashMap::new();
+            let offences = self.offences.get(kind).unwrap_or(&empty);
+            let total: usize = offences.values().sum();
+
+            println!(
+                "\n{}: {} lines, {} unparsable, {} offences from {} ips",
+                kind,
+                lines,
+                errors,
+                total,
+                offences.len()
+            );
+
+            let mut top: Vec<(&IpAddr, &usize)> = offences.iter().collect();
+            top.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+            for (ip, count) in top.iter().take(TOP_OFFENDERS) {
+                println!("  {:>6} {}", count, ip);
+            }
+        }
+    }
+}
+
+fn analyze(args: &Args, sources: &[(Kind, PathBuf)]) -> Result<Report> {
+    // historic logs only make sense with the time they were logged at, and can wait on
+    // hostnames to resolve
+    let parsers = Parsers::new(args, TimeSource::Event, Resolution::Blocking)?;
+    let jail = Jail::in_memory(args.allowance, args.jailtime);
+    // same allowances as when watching
+    let geoip = GeoIp::from_args(args)?;
+
+    let mut report = Report::default();
+    let mut events = vec![];
+
+    for (kind, path) in sources {
+        for file in rotated_files(path)? {
+            log!("analyzing {} logs at {:?}", kind, &file);
+
+            for line in open(&file)?.split(b'\n') {
+                let line = line?;
+                let line = String::from_utf8_lossy(&line);
+                *report.lines.entry(*kind).or_default() += 1;
+
+                let (offence, instant) = match parsers.parse(*kind, &line, None) {
+                    Ok(ParsingStatus::BadEntry(o)) => (o, false),
+                    Ok(ParsingStatus::InstantBan(o)) => (o, true),
+                    Ok(ParsingStatus::OkEntry) => continue,
+                    Err(e) => {
+                        if args.verbose {
+                            log!("ERR: {:?}", e);
+                        }
+                        *report.errors.entry(*kind).or_default() += 1;
+                        continue;
+                    }
+                };
+
+                let offenders = report.offences.entry(*kind).or_default();
+                *offenders.entry(offence.ip).or_default() += 1;
+
+                let (allowance, instant) = match &geoip {
+                    Some(g) => {
+                        let geo = g.lookup(offence.ip);
+                        (
+                            Some(g.allowance(&geo, args.allowance)),
+                            instant && g.allows_instant(&geo),
+                        )
+                    }
+                    None => (None, instant),
+                };
+
+                events.push(Event {
+                    kind: *kind,
+                    ip: offence.ip,
+                    ts: TimeSource::Event.pick(offence.ts),
+                    instant,
+                    allowance,
+                });
+            }
+        }
+    }
+
+    report.bans = replay(events, &jail, args.jailtime)?;
+    Ok(report)
+}
+
+pub fn run(args: &Args) -> Result<()> {
+    let sources = parsers::sources(args);
+    if sources.is_empty() {
+        bail!("no log files to analyze, see --help");
+    }
+    analyze(args, &sources)?.print(&sources);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns;
+    use crate::utils::{Cli, TempDir};
+    use clap::Parser;
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+    use std::str::FromStr;
+
+    #[test]
+    fn rotated() {
+        let tmp = TempDir::new("analyze");
+        let dir = &tmp.0;
+
+        let names = [
+            "auth.log",
+            "auth.log.1",
+            "auth.log.2.gz",
+            "auth.log.10.gz",
+            "other.log.1",
+        ];
+        for name in names {
+            let mut gz = GzEncoder::new(File::create(dir.join(name)).unwrap(), Compression::fast());
+            writeln!(gz, "{}", name).unwrap();
+            gz.finish().unwrap();
+        }
+
+        let files = rotated_files(&dir.join("auth.log")).unwrap();
+        let files: Vec<&str> = files
+            .iter()
+            .map(|f| f.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            files,
+            ["auth.log.10.gz", "auth.log.2.gz", "auth.log.1", "auth.log"]
+        );
+
+        let mut line = String::new();
+        open(&dir.join("auth.log.2.gz"))
+            .unwrap()
+            .read_line(&mut line)
+            .unwrap();
+        assert_eq!(line, "auth.log.2.gz\n");
+    }
+
+    #[test]
+    fn replayed() {
+        let jail = Jail::in_memory(3, 100);
+        let ip = IpAddr::from_str("8.8.8.8").unwrap();
+        let event = |ts: u64| Event {
+            kind: Kind::Sshd,
+            ip,
+            ts,
+            instant: false,
+            allowance: None,
+        };
+
+        // out of order, banned at the third offence, the one after is while jailed,
+        // and the last ones are after the jailtime
+        let events = vec![
+            event(30),
+            event(10),
+            event(20),
+            event(40),
+            event(200),
+            event(210),
+            event(220),
+        ];
+        let bans = replay(events, &jail, 100).unwrap();
+        assert_eq!(bans, vec![(30, Kind::Sshd, ip), (220, Kind::Sshd, ip)]);
+
+        // a raised allowance, e.g. a lenient network
+        let lenient = IpAddr::from_str("35.184.211.144").unwrap();
+        let events = (1..=4)
+            .map(|ts| Event {
+                ip: lenient,
+                allowance: Some(4),
+                ..event(ts)
+            })
+            .collect();
+        let bans = replay(events, &jail, 100).unwrap();
+        assert_eq!(bans, vec![(4, Kind::Sshd, lenient)]);
+    }
+
+    // within the runtime too, hostnames are resolved before moving on to the next line
+    #[tokio::test]
+    async fn resolved() {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = socket.local_addr().unwrap();
+        let handle = std::thread::spawn(move || dns::serve(socket, 1));
+
+        let dir = TempDir::new("analyze-resolved");
+        let path = dir.0.join("auth.log");
+        let line =
+            "2021-09-26T06:25:19+00:00 host pam: authentication failure; rhost=scanner.example.net";
+        std::fs::write(&path, format!("{}\n{}\n", line, line)).unwrap();
+
+        let cli = Cli::parse_from([
+            "blockfast".to_string(),
+            format!("--generic-logpath={}", path.display()),
+            "--generic-ip=rhost=(\\S+)".to_string(),
+            "--generic-positive=authentication failure".to_string(),
+            format!("--resolver={}", server),
+        ]);
+        let sources = parsers::sources(&cli.args);
+        let report = analyze(&cli.args, &sources).unwrap();
+
+        assert_eq!(report.errors.get(&Kind::Generic), None);
+        let offences = &report.offences[&Kind::Generic];
+        assert_eq!(offences[&IpAddr::from_str("192.0.2.1").unwrap()], 2);
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,334 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::result::Result::Ok;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use anyhow::*;
+use lazy_static::lazy_static;
+
+use crate::jail::exec;
+use crate::utils::log;
+
+const SET_NAME: &str = "blockfast_blocklist";
+
+// cheap enough to stat the files this often
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    // never blocked, lists like FireHOL level1 carry them as bogons but dropping them cuts the
+    // host off from itself and its lan
+    static ref LOCAL: Vec<Net> = [
+        "0.0.0.0/8",
+        "10.0.0.0/8",
+        "100.64.0.0/10",
+        "127.0.0.0/8",
+        "169.254.0.0/16",
+        "172.16.0.0/12",
+        "192.168.0.0/16",
+        "::/128",
+        "::1/128",
+        "fc00::/7",
+        "fe80::/10",
+    ]
+    .iter()
+    .map(|n| Net::from_str(n).unwrap())
+    .collect();
+}
+
+/// A network, host bits cleared
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Net {
+    ip: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for Net {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Net> {
+        let (ip, prefix) = match s.split_once('/') {
+            Some((ip, prefix)) => (ip, Some(prefix)),
+            None => (s, None),
+        };
+        let ip = IpAddr::from_str(ip).map_err(|_| anyhow!("invalid ip {:?}", ip))?;
+        let max = if ip.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => u8::from_str(p)
+                .ok()
+                .filter(|p| *p > 0 && *p <= max)
+                .ok_or_else(|| anyhow!("invalid prefix {:?}", p))?,
+            None => max,
+        };
+
+        Ok(Net::masked(ip, prefix))
+    }
+}
+
+impl Net {
+    fn masked(ip: IpAddr, prefix: u8) -> Net {
+        let ip = match ip {
+            IpAddr::V4(ip) => {
+                let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+                IpAddr::from((u32::from(ip) & mask).to_be_bytes())
+            }
+            IpAddr::V6(ip) => {
+                let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+                IpAddr::from((u128::from(ip) & mask).to_be_bytes())
+            }
+        };
+        Net { ip, prefix }
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        ip.is_ipv4() == self.ip.is_ipv4() && Net::masked(ip, self.prefix) == *self
+    }
+
+    fn overlaps(&self, other: &Net) -> bool {
+        self.contains(other.ip) || other.contains(self.ip)
+    }
+}
+
+impl fmt::Display for Net {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.ip, self.prefix)
+    }
+}
+
+/// Plain ip or cidr lines, `#` and `;` comments - Spamhaus DROP has `net ; SBL id` lines.
+/// Bad lines and local ranges are skipped, and counted in a single log line
+pub fn parse(content: &str, origin: &str) -> HashSet<Net> {
+    let mut nets = HashSet::new();
+    let (mut local, mut bad) = (0, 0);
+    for line in content.lines() {
+        let line = line.split(['#', ';']).next().unwrap_or("");
+        let entry = match line.split_whitespace().next() {
+            Some(e) => e,
+            None => continue,
+        };
+        match Net::from_str(entry) {
+            Ok(net) if LOCAL.iter().any(|l| l.overlaps(&net)) => local += 1,
+            Ok(net) => {
+                nets.insert(net);
+            }
+            Err(_) => bad += 1,
+        }
+    }
+    if local + bad > 0 {
+        log!(
+            "blocklist {} skipping {} local ranges and {} bad lines",
+            origin,
+            local,
+            bad
+        );
+    }
+    nets
+}
+
+pub fn load(paths: &[PathBuf]) -> Result<HashSet<Net>> {
+    let mut nets = HashSet::new();
+    for path in paths {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("cant read blocklist {:?}", path))?;
+        nets.extend(parse(&content, &path.to_string_lossy()));
+    }
+    Ok(nets)
+}
+
+/// Entries to add and to remove to go from one list to the other
+pub fn diff<'a>(old: &'a HashSet<Net>, new: &'a HashSet<Net>) -> (Vec<&'a Net>, Vec<&'a Net>) {
+    (new.difference(old).collect(), old.difference(new).collect())
+}
+
+/// Permanent set, next to the jail's
+pub struct Blocklist {
+    paths: Vec<PathBuf>,
+    nets: HashSet<Net>,
+    modified: Vec<Option<SystemTime>>,
+}
+
+impl Blocklist {
+    pub fn new(paths: Vec<PathBuf>) -> Result<Blocklist> {
+        const ERR_MSG: &str = "error using ipset/iptables, maybe it's not installed, or this program isn't running as root ?";
+
+        // ipset sets are single family, like the jail's
+        let cmd = format!("create -exist {} hash:net", SET_NAME);
+        exec("ipset", &cmd, ERR_MSG)?;
+        exec("ipset", &format!("flush {}", SET_NAME), ERR_MSG)?;
+
+        // the rules outlive the process, restarts would stack them
+        for chain in ["INPUT", "FORWARD"] {
+            let rule = format!("-m set -j DROP --match-set {} src", SET_NAME);
+            if exec("iptables", &format!("-C {} {}", chain, rule), ERR_MSG).is_err() {
+                exec("iptables", &format!("-I {} 1 {}", chain, rule), ERR_MSG)?;
+            }
+        }
+
+        let mut blocklist = Blocklist {
+            paths,
+            nets: HashSet::new(),
+            modified: vec![],
+        };
+        blocklist.reload()?;
+        Ok(blocklist)
+    }
+
+    fn mtimes(&self) -> Vec<Option<SystemTime>> {
+        self.paths
+            .iter()
+            .map(|p| std::fs::metadata(p).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+
+    pub fn reload(&mut self) -> Result<()> {
+        self.modified = self.mtimes();
+        let (v4, v6): (HashSet<Net>, HashSet<Net>) =
+            load(&self.paths)?.into_iter().partition(|n| n.ip.is_ipv4());
+        if !v6.is_empty() {
+            log!("blocklist skipping {} IPv6 entries", v6.len());
+        }
+
+        let (added, removed) = diff(&self.nets, &v4);
+        let mut batch = String::new();
+        for net in &added {
+            batch.push_str(&format!("add -exist {} {}\n", SET_NAME, net));
+        }
+        for net in &removed {
+            batch.push_str(&format!("del -exist {} {}\n", SET_NAME, net));
+        }
+        restore(&batch)?;
+
+        log!(
+            "blocklist loaded, {} added, {} removed, {} entries",
+            added.len(),
+            removed.len(),
+            v4.len()
+        );
+        self.nets = v4;
+        Ok(())
+    }
+
+    /// Reloads on file changes, and every `refresh` seconds regardless
+    pub async fn watch(mut self, refresh: u64) {
+        let mut since_reload = Duration::ZERO;
+        loop {
+            tokio::time::sleep(CHECK_INTERVAL).await;
+            since_reload += CHECK_INTERVAL;
+            if self.mtimes() == self.modified && since_reload.as_secs() < refresh {
+                continue;
+            }
+            since_reload = Duration::ZERO;
+            // a list being rewritten or gone for a moment keeps the previous entries
+            if let Err(e) = self.reload() {
+                log!("ERR: reloading blocklist {:?}", e);
+            }
+        }
+    }
+}
+
+// one process for the whole batch, lists have thousands of entries
+fn restore(batch: &str) -> Result<()> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    let mut child = Command::new("ipset")
+        .arg("restore")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow!("cant write to ipset"))?
+        .write_all(batch.as_bytes())?;
+    let out = child.wait_with_output()?;
+    ensure!(out.status.success(), "err exec ipset restore, {:?}", out);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+
+    fn net(s: &str) -> Net {
+        Net::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn nets() {
+        assert_eq!(net("1.10.16.0/20").to_string(), "1.10.16.0/20");
+        assert_eq!(net("179.124.36.195").to_string(), "179.124.36.195/32");
+        assert_eq!(net("179.124.36.195/24").to_string(), "179.124.36.0/24");
+        assert_eq!(net("2001:db8::1/32").to_string(), "2001:db8::/32");
+
+        assert!(net("179.124.36.0/24").contains("179.124.36.195".parse().unwrap()));
+        assert!(!net("179.124.36.0/24").contains("179.124.37.1".parse().unwrap()));
+        assert!(!net("0.0.0.0/1").contains("::1".parse().unwrap()));
+
+        assert!(Net::from_str("179.124.36.195/33").is_err());
+        assert!(Net::from_str("179.124.36/24").is_err());
+        assert!(Net::from_str("2001:db8::/129").is_err());
+        assert!(Net::from_str("0.0.0.0/0").is_err());
+        assert!(Net::from_str("::/0").is_err());
+    }
+
+    #[test]
+    fn formats() {
+        let content = "\
+; Spamhaus DROP List 2021/09/26 - (c) 2021 The Spamhaus Project
+; Last-Modified: Sun, 26 Sep 2021 06:25:19 GMT
+1.10.16.0/20 ; SBL256894
+1.19.0.0/16 ; SBL434604
+
+# FireHOL level1
+0.0.0.0/8
+10.0.0.0/8
+100.64.0.0/10
+100.100.100.100
+192.168.1.1
+127.0.0.0/8
+169.254.0.0/16
+172.0.0.0/8
+fe80::/10
+179.124.36.195
+not-an-ip
+179.124.36.0/24 # shared list, same net written another way
+179.124.36.7/24
+";
+        let nets = parse(content, "test");
+        assert_eq!(nets.len(), 4);
+        assert!(nets.contains(&net("1.10.16.0/20")));
+        assert!(nets.contains(&net("179.124.36.195/32")));
+        assert!(nets.contains(&net("179.124.36.0/24")));
+    }
+
+    #[test]
+    fn changes() {
+        let old = parse("1.10.16.0/20\n1.19.0.0/16\n", "old");
+        let new = parse("1.19.0.0/16\n35.184.211.0/24\n", "new");
+
+        let (added, removed) = diff(&old, &new);
+        assert_eq!(added, vec![&net("35.184.211.0/24")]);
+        assert_eq!(removed, vec![&net("1.10.16.0/20")]);
+
+        let (added, removed) = diff(&new, &new);
+        assert!(added.is_empty() && removed.is_empty());
+    }
+
+    #[test]
+    fn files() {
+        let dir = TempDir::new("blocklist");
+        let drop = dir.0.join("drop.txt");
+        let own = dir.0.join("own.txt");
+        std::fs::write(&drop, "1.10.16.0/20 ; SBL256894\n").unwrap();
+        std::fs::write(&own, "179.124.36.195\n1.10.16.0/20\n").unwrap();
+
+        assert_eq!(load(&[drop.clone(), own]).unwrap().len(), 2);
+        assert!(load(&[drop, dir.0.join("missing.txt")]).is_err());
+    }
+}
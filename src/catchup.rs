@@ -0,0 +1,148 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::result::Result::Ok;
+use std::time::UNIX_EPOCH;
+
+use anyhow::*;
+
+use crate::analyze;
+use crate::parsers::{Kind, Parsers};
+use crate::utils::{log, ParsingStatus};
+
+fn modified(path: &Path) -> Option<u64> {
+    let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    modified
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|d| d.as_secs())
+}
+
+fn collect(
+    reader: impl BufRead,
+    since: u64,
+    kind: Kind,
+    parsers: &Parsers,
+    offences: &mut Vec<ParsingStatus>,
+) -> Result<()> {
+    for line in reader.split(b'\n') {
+        let line = line?;
+        let line = String::from_utf8_lossy(&line);
+
+        // unparsable lines are already reported when tailing
+        let ret = match parsers.parse(kind, &line, None) {
+            Ok(ret) => ret,
+            Err(_) => continue,
+        };
+
+        let ts = match &ret {
+            ParsingStatus::BadEntry(o) | ParsingStatus::InstantBan(o) => o.ts,
+            ParsingStatus::OkEntry => continue,
+        };
+
+        if ts.is_some_and(|ts| ts >= since) {
+            offences.push(ret);
+        }
+    }
+    Ok(())
+}
+
+/// Offences logged since `since` in the rotated versions of a logfile, then in the first
+/// `len` bytes of it. Lines without a timestamp can't be placed in time, and are skipped, so
+/// are the unreadable rotated files, e.g. a truncated .gz from an interrupted logrotate.
+pub fn read(
+    path: &Path,
+    len: u64,
+    since: u64,
+    kind: Kind,
+    parsers: &Parsers,
+) -> Result<Vec<ParsingStatus>> {
+    let mut offences = vec![];
+
+    // oldest first, the ones last written to before the window are skipped
+    let mut rotated = analyze::rotated_files(path).unwrap_or_default();
+    rotated.pop();
+    for file in rotated {
+        if modified(&file).is_some_and(|m| m < since) {
+            continue;
+        }
+        let ret = analyze::open(&file)
+            .and_then(|reader| collect(reader, since, kind, parsers, &mut offences));
+        if let Err(e) = ret {
+            log!("ERR: catching up on {:?}, skipping it {:?}", file, e);
+        }
+    }
+
+    // created after starting, nothing logged before
+    if len == 0 {
+        return Ok(offences);
+    }
+
+    let file = File::open(path).with_context(|| format!("cant open {:?}", path))?;
+    collect(
+        BufReader::new(file.take(len)),
+        since,
+        kind,
+        parsers,
+        &mut offences,
+    )?;
+    Ok(offences)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::Resolution;
+    use crate::utils::TempDir;
+    use crate::utils::{Cli, TimeSource};
+    use clap::Parser;
+    use std::io::Write;
+
+    #[test]
+    fn window() {
+        let dir = TempDir::new("catchup");
+        let path = dir.0.join("auth.log");
+        let mut file = File::create(&path).unwrap();
+        let lines = [
+            "2021-09-26T06:00:00+00:00 livecompute sshd[23254]: Invalid user neal from 1.1.1.1",
+            "2021-09-26T06:20:00+00:00 livecompute sshd[23254]: Invalid user neal from 2.2.2.2",
+            "2021-09-26T06:21:00+00:00 livecompute sshd[23246]: successful login 3.3.3.3 port 41883 ssh2",
+            "livecompute sshd[23254]: Invalid user neal from 4.4.4.4",
+            "2021-09-26T06:22:00+00:00 livecompute sshd[23254]: Invalid user neal from 5.5.5.5",
+            "2021-09-26T06:23:00+00:00 livecompute sshd[23254]: Invalid user neal from 6.6.6.6",
+        ];
+        for line in lines {
+            writeln!(file, "{}", line).unwrap();
+        }
+
+        // rotated away before starting
+        let rotated = dir.0.join("auth.log.1");
+        std::fs::write(
+            &rotated,
+            "2021-09-26T05:00:00+00:00 livecompute sshd[23254]: Invalid user neal from 7.7.7.7\n\
+             2021-09-26T06:10:00+00:00 livecompute sshd[23254]: Invalid user neal from 8.8.8.8\n",
+        )
+        .unwrap();
+
+        // the last line was logged after the catch-up started
+        let len = std::fs::metadata(&path).unwrap().len() - lines[5].len() as u64 - 1;
+        let since = 1632636000; // 06:00:00
+        let args = Cli::parse_from(["blockfast", "-s=/dev/null"]).args;
+        let parsers = Parsers::new(&args, TimeSource::Event, Resolution::Blocking).unwrap();
+
+        let ret = read(&path, len, since + 60, Kind::Sshd, &parsers).unwrap();
+        let ips: Vec<String> = ret
+            .iter()
+            .map(|r| match r {
+                ParsingStatus::BadEntry(o) => o.ip.to_string(),
+                _ => panic!("bad parsing"),
+            })
+            .collect();
+        assert_eq!(ips, ["8.8.8.8", "2.2.2.2", "5.5.5.5"]);
+
+        // a corrupt rotated file is skipped, the rest is still read
+        std::fs::write(dir.0.join("auth.log.2.gz"), b"\x1f\x8b\x08not gzip").unwrap();
+        let ret = read(&path, len, since + 60, Kind::Sshd, &parsers).unwrap();
+        assert_eq!(ret.len(), 3);
+    }
+}
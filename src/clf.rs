@@ -1,3 +1,5 @@
+use crate::http::{self, HttpRequest, HttpRules};
+use crate::timestamp;
 use crate::utils::ParsingStatus;
 use anyhow::*;
 use lazy_static::lazy_static;
@@ -6,11 +8,13 @@ use std::{net::IpAddr, str::FromStr};
 
 lazy_static! {
     static ref RE_IP: Regex = Regex::new(r"^(\S+)\s").unwrap();
-    static ref RE_STATUS: Regex = Regex::new(r"(\d+)\s(\w+)$").unwrap();
+    static ref RE_REQUEST: Regex = Regex::new(r#""\S+\s(\S+)[^"]*""#).unwrap();
+    static ref RE_STATUS: Regex = Regex::new(r#""\s(\d+)\s(\S+)"#).unwrap();
+    static ref RE_USER_AGENT: Regex = Regex::new(r#""([^"]*)"$"#).unwrap();
 }
 
 #[allow(clippy::bind_instead_of_map)]
-pub fn parse(line: &str, valid_statuses: &[u32]) -> Result<ParsingStatus> {
+pub fn request(line: &str) -> Result<HttpRequest> {
     let ip = RE_IP
         .captures(line)
         .and_then(|c| c.get(1))
@@ -25,12 +29,36 @@ pub fn parse(line: &str, valid_statuses: &[u32]) -> Result<ParsingStatus> {
         .and_then(|e| e.parse::<u32>().ok())
         .ok_or_else(|| anyhow!("cant parse clf line - status"))?;
 
-    let is_good_status = valid_statuses.iter().any(|s| s == &status);
-    if !is_good_status {
-        return Ok(ParsingStatus::BadEntry(ip));
-    }
+    let path = RE_REQUEST
+        .captures(line)
+        .and_then(|c| c.get(1))
+        .map(|g| g.as_str().to_string());
 
-    Ok(ParsingStatus::OkEntry)
+    // only present in the combined log format
+    let user_agent = RE_USER_AGENT
+        .captures(line)
+        .and_then(|c| c.get(1))
+        .map(|g| g.as_str().to_string());
+
+    Ok(HttpRequest {
+        ip,
+        path,
+        user_agent,
+        status,
+        ts: timestamp::clf(line),
+    })
+}
+
+/// `source_ts` is the time given by the source, if the line has none
+pub fn parse(
+    line: &str,
+    valid_statuses: &[u32],
+    rules: &HttpRules,
+    source_ts: Option<u64>,
+) -> Result<ParsingStatus> {
+    let mut req = request(line)?;
+    req.ts = req.ts.or(source_ts);
+    http::assess(&req, valid_statuses, rules)
 }
 
 #[cfg(test)]
@@ -45,7 +73,7 @@ mod tests {
         ];
 
         vectors.iter().for_each(|e| {
-            let ret = parse(*e, &vec![200, 404]).unwrap();
+            let ret = parse(e, &[200, 404], &HttpRules::default(), None).unwrap();
             match ret {
                 ParsingStatus::BadEntry(_) => {}
                 _ => panic!("bad parsing"),
@@ -61,7 +89,7 @@ mod tests {
         ];
 
         vectors.iter().for_each(|e| {
-            let ret = parse(*e, &vec![200, 404]).unwrap();
+            let ret = parse(e, &[200, 404], &HttpRules::default(), None).unwrap();
             match ret {
                 ParsingStatus::OkEntry => {}
                 _ => panic!("bad parsing"),
@@ -77,8 +105,25 @@ mod tests {
         ];
 
         vectors.iter().for_each(|e| {
-            let ret = parse(*e, &vec![200, 404]);
+            let ret = parse(e, &[200, 404], &HttpRules::default(), None);
             assert!(ret.is_err());
         })
     }
+
+    #[test]
+    fn combined() {
+        let line = "8.8.8.8 - - [25/Sep/2021:13:49:56 +0200] \"GET /.env HTTP/1.1\" 404 - \"-\" \"zgrab/0.x\"";
+
+        let req = request(line).unwrap();
+        assert_eq!(req.status, 404);
+        assert_eq!(req.path.as_deref(), Some("/.env"));
+        assert_eq!(req.user_agent.as_deref(), Some("zgrab/0.x"));
+        assert_eq!(req.ts, Some(1632570596));
+
+        let rules = HttpRules::new(true, &[], &[], false).unwrap();
+        match parse(line, &[200, 404], &rules, None).unwrap() {
+            ParsingStatus::BadEntry(_) => {}
+            _ => panic!("bad parsing"),
+        }
+    }
 }
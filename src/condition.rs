@@ -0,0 +1,91 @@
+use anyhow::*;
+use regex::Regex;
+
+#[derive(Clone, Debug)]
+pub enum Test {
+    Equals(String),
+    Matches(Regex),
+    // inclusive
+    Range(f64, f64),
+}
+
+/// A field condition, as `field=value`, `field~regex` or `field=min..max`
+#[derive(Clone, Debug)]
+pub struct Condition {
+    pub field: String,
+    pub test: Test,
+}
+
+fn parse_range(value: &str) -> Option<(f64, f64)> {
+    let (min, max) = value.split_once("..")?;
+    Some((min.parse().ok()?, max.parse().ok()?))
+}
+
+pub fn parse_condition(a: &str) -> Result<Condition> {
+    let split = a.find(['=', '~']).filter(|i| *i > 0).ok_or_else(|| {
+        anyhow!(
+            "invalid condition {:?}, expected field=value, field~regex or field=min..max",
+            a
+        )
+    })?;
+    let (field, op, value) = (&a[..split], &a[split..split + 1], &a[split + 1..]);
+
+    let test = match (op, parse_range(value)) {
+        ("~", _) => Test::Matches(
+            Regex::new(value).with_context(|| format!("invalid condition regex {:?}", value))?,
+        ),
+        (_, Some((min, max))) => Test::Range(min, max),
+        _ => Test::Equals(value.to_string()),
+    };
+
+    Ok(Condition {
+        field: field.to_string(),
+        test,
+    })
+}
+
+impl Condition {
+    /// A missing field never matches
+    pub fn matches(&self, value: Option<&str>) -> bool {
+        let value = match value {
+            Some(value) => value,
+            None => return false,
+        };
+        match &self.test {
+            Test::Equals(v) => value == v,
+            Test::Matches(re) => re.is_match(value),
+            Test::Range(min, max) => value.parse::<f64>().is_ok_and(|v| v >= *min && v <= *max),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conditions() {
+        let c = parse_condition("event.outcome=failure").unwrap();
+        assert_eq!(c.field, "event.outcome");
+        assert!(c.matches(Some("failure")));
+        assert!(!c.matches(Some("success")));
+        assert!(!c.matches(None));
+
+        let c = parse_condition("msg~(?i)login failed").unwrap();
+        assert!(c.matches(Some("Login failed for neal")));
+
+        let c = parse_condition("status=400..499").unwrap();
+        assert!(c.matches(Some("401")));
+        assert!(c.matches(Some("499")));
+        assert!(!c.matches(Some("500")));
+        assert!(!c.matches(Some("nope")));
+
+        // an equal sign in the value
+        let c = parse_condition("query=a=b").unwrap();
+        assert!(c.matches(Some("a=b")));
+
+        assert!(parse_condition("=failure").is_err());
+        assert!(parse_condition("outcome").is_err());
+        assert!(parse_condition("msg~(").is_err());
+    }
+}
@@ -0,0 +1,560 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::path::Path;
+use std::result::Result::Ok;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::*;
+
+use crate::utils::log;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_AAAA: u16 = 28;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_PACKET: usize = 1232;
+
+// how long a failed resolution is remembered (seconds)
+pub const NEGATIVE_TTL: u64 = 300;
+
+// prune expired entries past this many cached names
+const MAX_CACHE: usize = 10_000;
+
+/// Lines skipped while their hostname resolves in the background are parsed again after
+/// this, once both the A and AAAA queries had time to answer or time out
+pub const PENDING_RETRY: Duration = Duration::from_secs(5);
+
+/// How names missing from the cache are resolved
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resolution {
+    /// right away, the line waits for the answer - e.g. analyzing or catching up on logs
+    Blocking,
+    /// in the background, the line is skipped until the answer comes, see [Pending]
+    Background,
+}
+
+/// A name being resolved in the background
+#[derive(Debug)]
+pub struct Pending(String);
+
+impl std::fmt::Display for Pending {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "resolving {:?}, pending", self.0)
+    }
+}
+
+impl std::error::Error for Pending {}
+
+pub fn is_pending(e: &Error) -> bool {
+    e.chain().any(|c| c.is::<Pending>())
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Rdata {
+    Ip(IpAddr),
+    Name(String),
+    Other,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Record {
+    pub rtype: u16,
+    pub ttl: u32,
+    pub data: Rdata,
+}
+
+/// DNS server address, port 53 if omitted, e.g. 1.1.1.1 or 127.0.0.53:53
+pub fn parse_resolver(a: &str) -> Result<SocketAddr> {
+    if let Ok(ip) = IpAddr::from_str(a) {
+        return Ok(SocketAddr::new(ip, 53));
+    }
+    SocketAddr::from_str(a).with_context(|| format!("invalid resolver address {:?}", a))
+}
+
+// hosts file format, `ip name [aliases...]` with # comments
+fn parse_hosts(content: &str) -> HashMap<String, IpAddr> {
+    let mut hosts = HashMap::new();
+    for line in content.lines() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut fields = line.split_whitespace();
+        let ip = match fields.next().and_then(|ip| IpAddr::from_str(ip).ok()) {
+            Some(ip) => ip,
+            None => continue,
+        };
+        for name in fields {
+            // first entry wins, like the libc resolver
+            hosts.entry(name.to_lowercase()).or_insert(ip);
+        }
+    }
+    hosts
+}
+
+fn encode_query(id: u16, name: &str, qtype: u16) -> Result<Vec<u8>> {
+    // header: id, recursion desired, one question
+    let mut buf = vec![];
+    buf.extend(id.to_be_bytes());
+    buf.extend([0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+
+    for label in name.trim_end_matches('.').split('.') {
+        ensure!(
+            !label.is_empty() && label.len() < 64,
+            "invalid dns name {:?}",
+            name
+        );
+        buf.push(label.len() as u8);
+        buf.extend(label.as_bytes());
+    }
+    buf.push(0);
+
+    buf.extend(qtype.to_be_bytes());
+    buf.extend(1u16.to_be_bytes());
+    Ok(buf)
+}
+
+fn read_u16(buf: &[u8], pos: usize) -> Result<u16> {
+    let b = buf
+        .get(pos..pos + 2)
+        .ok_or_else(|| anyhow!("truncated dns response"))?;
+    Ok(u16::from_be_bytes([b[0], b[1]]))
+}
+
+fn read_u32(buf: &[u8], pos: usize) -> Result<u32> {
+    let b = buf
+        .get(pos..pos + 4)
+        .ok_or_else(|| anyhow!("truncated dns response"))?;
+    Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+// a possibly compressed name, and the position right after it
+fn read_name(buf: &[u8], mut pos: usize) -> Result<(String, usize)> {
+    let mut labels = vec![];
+    let mut end = None;
+
+    // bounded, compression pointers could loop
+    for _ in 0..128 {
+        let len = *buf
+            .get(pos)
+            .ok_or_else(|| anyhow!("truncated dns response"))? as usize;
+        match len {
+            0 => {
+                return Ok((labels.join("."), end.unwrap_or(pos + 1)));
+            }
+            l if l & 0xc0 == 0xc0 => {
+                let pointer = read_u16(buf, pos)? as usize & 0x3fff;
+                end.get_or_insert(pos + 2);
+                pos = pointer;
+            }
+            l => {
+                let label = buf
+                    .get(pos + 1..pos + 1 + l)
+                    .ok_or_else(|| anyhow!("truncated dns response"))?;
+                labels.push(String::from_utf8_lossy(label).to_lowercase());
+                pos += 1 + l;
+            }
+        }
+    }
+    bail!("dns name compression loop")
+}
+
+// answers to `query`, same id and same question
+fn decode_response(buf: &[u8], query: &[u8]) -> Result<Vec<Record>> {
+    ensure!(buf.get(..2) == query.get(..2), "dns response id mismatch");
+    let flags = read_u16(buf, 2)?;
+    ensure!(flags & 0x8000 != 0, "not a dns response");
+    let question = buf
+        .get(12..query.len())
+        .ok_or_else(|| anyhow!("truncated dns response"))?;
+    ensure!(
+        read_u16(buf, 4)? == 1 && question.eq_ignore_ascii_case(&query[12..]),
+        "dns response question mismatch"
+    );
+
+    // NXDOMAIN is an empty answer, other errors are errors
+    match flags & 0x000f {
+        0 | 3 => {}
+        rcode => bail!("dns error, rcode {}", rcode),
+    }
+
+    let questions = read_u16(buf, 4)?;
+    let answers = read_u16(buf, 6)?;
+
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = read_name(buf, pos)?.1 + 4;
+    }
+
+    let mut records = vec![];
+    for _ in 0..answers {
+        pos = read_name(buf, pos)?.1;
+        let rtype = read_u16(buf, pos)?;
+        let ttl = read_u32(buf, pos + 4)?;
+        let len = read_u16(buf, pos + 8)? as usize;
+        let start = pos + 10;
+        let rdata = buf
+            .get(start..start + len)
+            .ok_or_else(|| anyhow!("truncated dns response"))?;
+
+        let data = match (rtype, len) {
+            (TYPE_A, 4) => Rdata::Ip(IpAddr::V4(Ipv4Addr::new(
+                rdata[0], rdata[1], rdata[2], rdata[3],
+            ))),
+            (TYPE_AAAA, 16) => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(rdata);
+                Rdata::Ip(IpAddr::V6(Ipv6Addr::from(octets)))
+            }
+            (TYPE_PTR, _) => Rdata::Name(read_name(buf, start)?.0),
+            _ => Rdata::Other,
+        };
+        records.push(Record { rtype, ttl, data });
+        pos = start + len;
+    }
+
+    Ok(records)
+}
+
+// unpredictable, off-path spoofing has to guess it along with the source port.
+// std hashers are keyed from the os random source
+fn random_id() -> u16 {
+    RandomState::new().build_hasher().finish() as u16
+}
+
+fn bind_addr(server: SocketAddr) -> SocketAddr {
+    match server {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    }
+}
+
+/// Answers of a recursive DNS server to a single question, blocking - for offline use
+pub fn query(server: SocketAddr, name: &str, qtype: u16) -> Result<Vec<Record>> {
+    let packet = encode_query(random_id(), name, qtype)?;
+
+    let socket = UdpSocket::bind(bind_addr(server))?;
+    socket.set_read_timeout(Some(TIMEOUT))?;
+    socket.connect(server)?;
+    socket.send(&packet)?;
+
+    let mut buf = vec![0; MAX_PACKET];
+    let len = socket
+        .recv(&mut buf)
+        .with_context(|| format!("no dns response from {}", server))?;
+    decode_response(&buf[..len], &packet)
+}
+
+/// Same, without blocking the runtime
+pub async fn query_async(server: SocketAddr, name: &str, qtype: u16) -> Result<Vec<Record>> {
+    let packet = encode_query(random_id(), name, qtype)?;
+
+    let socket = tokio::net::UdpSocket::bind(bind_addr(server)).await?;
+    socket.connect(server).await?;
+    socket.send(&packet).await?;
+
+    let mut buf = vec![0; MAX_PACKET];
+    let len = tokio::time::timeout(TIMEOUT, socket.recv(&mut buf))
+        .await
+        .map_err(|_| anyhow!("no dns response from {}", server))??;
+    decode_response(&buf[..len], &packet)
+}
+
+// first address of a name and its ttl. Failures are remembered like unknown names,
+// a broken zone shouldn't be queried on every line
+fn first_ip(answers: Result<Vec<Record>>, name: &str) -> Option<Option<(IpAddr, u64)>> {
+    match answers {
+        Ok(records) => Some(records.iter().find_map(|r| match r.data {
+            Rdata::Ip(ip) => Some((ip, r.ttl as u64)),
+            _ => None,
+        })),
+        Err(e) => {
+            log!("WARN: cant resolve {:?}, {:?}", name, e);
+            None
+        }
+    }
+}
+
+async fn resolve(server: SocketAddr, name: &str) -> Option<(IpAddr, u64)> {
+    for qtype in [TYPE_A, TYPE_AAAA] {
+        match first_ip(query_async(server, name, qtype).await, name) {
+            Some(None) => continue,
+            Some(found) => return found,
+            None => return None,
+        }
+    }
+    None
+}
+
+fn resolve_blocking(server: SocketAddr, name: &str) -> Option<(IpAddr, u64)> {
+    for qtype in [TYPE_A, TYPE_AAAA] {
+        match first_ip(query(server, name, qtype), name) {
+            Some(None) => continue,
+            Some(found) => return found,
+            None => return None,
+        }
+    }
+    None
+}
+
+enum Cached {
+    Resolved(Option<IpAddr>, u64),
+    Pending,
+}
+
+type Cache = Arc<Mutex<HashMap<String, Cached>>>;
+
+fn store(cache: &Cache, name: String, resolved: Option<(IpAddr, u64)>, now: u64) {
+    let ip = resolved.map(|(ip, _)| ip);
+    let ttl = resolved.map(|(_, ttl)| ttl).unwrap_or(NEGATIVE_TTL);
+    if let Ok(mut cache) = cache.lock() {
+        cache.insert(name, Cached::Resolved(ip, now + ttl));
+    }
+}
+
+/// Resolves hostnames against a hosts file, then a DNS server, see [Resolution]
+pub struct Resolver {
+    hosts: HashMap<String, IpAddr>,
+    server: Option<SocketAddr>,
+    resolution: Resolution,
+    cache: Cache,
+    pruned: AtomicU64,
+}
+
+impl Resolver {
+    pub fn new(
+        hosts_file: Option<&Path>,
+        server: Option<SocketAddr>,
+        resolution: Resolution,
+    ) -> Result<Resolver> {
+        let hosts = match hosts_file {
+            Some(p) => parse_hosts(
+                &std::fs::read_to_string(p).with_context(|| format!("cant read {:?}", p))?,
+            ),
+            None => HashMap::new(),
+        };
+
+        Ok(Resolver {
+            hosts,
+            server,
+            resolution,
+            cache: Arc::new(Mutex::new(HashMap::new())),
+            pruned: AtomicU64::new(0),
+        })
+    }
+
+    pub fn lookup(&self, name: &str, now: u64) -> Result<Option<IpAddr>> {
+        let name = name.trim_end_matches('.').to_lowercase();
+        if let Some(ip) = self.hosts.get(&name) {
+            return Ok(Some(*ip));
+        }
+
+        let server = match self.server {
+            Some(server) => server,
+            None => return Ok(None),
+        };
+
+        {
+            let mut cache = self.cache.lock().map_err(|_| anyhow!("cant lock"))?;
+            match cache.get(&name) {
+                Some(Cached::Resolved(ip, expires)) if *expires > now => return Ok(*ip),
+                Some(Cached::Pending) => return Err(Pending(name).into()),
+                _ => {}
+            }
+
+            // at most once per negative ttl, not to rescan the cache on every line
+            let pruned = self.pruned.load(Ordering::Relaxed);
+            if cache.len() > MAX_CACHE && now >= pruned + NEGATIVE_TTL {
+                self.pruned.store(now, Ordering::Relaxed);
+                cache.retain(|_, c| !matches!(c, Cached::Resolved(_, expires) if *expires <= now));
+            }
+            cache.insert(name.clone(), Cached::Pending);
+        }
+
+        let handle = match tokio::runtime::Handle::try_current() {
+            Ok(handle) if self.resolution == Resolution::Background => handle,
+            _ => {
+                let resolved = resolve_blocking(server, &name);
+                store(&self.cache, name, resolved, now);
+                return Ok(resolved.map(|(ip, _)| ip));
+            }
+        };
+
+        let cache = self.cache.clone();
+        let pending = name.clone();
+        handle.spawn(async move {
+            let resolved = resolve(server, &pending).await;
+            store(&cache, pending, resolved, now);
+        });
+        Err(Pending(name).into())
+    }
+}
+
+/// Test server answering every A question with 192.0.2.1, through a compression pointer to
+/// the question. Names with a "fail" label get a SERVFAIL
+#[cfg(test)]
+pub fn serve(socket: UdpSocket, answers: usize) {
+    let mut buf = [0; 512];
+    for _ in 0..answers {
+        let (len, peer) = socket.recv_from(&mut buf).unwrap();
+        let mut res = buf[..len].to_vec();
+        let qtype = read_u16(&res, len - 4).unwrap();
+        res[2] = 0x81;
+        res[3] = 0x80;
+        if res.windows(5).any(|w| w == b"\x04fail") {
+            res[3] = 0x82;
+        } else if qtype == TYPE_A {
+            res[7] = 1;
+            res.extend([0xc0, 12, 0, 1, 0, 1, 0, 0, 0x0e, 0x10, 0, 4, 192, 0, 2, 1]);
+        }
+        socket.send_to(&res, peer).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+
+    #[test]
+    fn hosts() {
+        let hosts = parse_hosts(
+            "# comment\n127.0.0.1 localhost\n\n192.0.2.7 Scanner.example.net scanner # alias\n192.0.2.8 scanner\nnope host\n",
+        );
+        assert_eq!(hosts.len(), 3);
+        assert_eq!(
+            hosts["scanner.example.net"],
+            "192.0.2.7".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(hosts["scanner"], "192.0.2.7".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn resolvers() {
+        assert_eq!(
+            parse_resolver("1.1.1.1").unwrap(),
+            "1.1.1.1:53".parse().unwrap()
+        );
+        assert_eq!(
+            parse_resolver("[::1]:5353").unwrap(),
+            "[::1]:5353".parse().unwrap()
+        );
+        assert!(parse_resolver("dns.example.net").is_err());
+    }
+
+    #[test]
+    fn messages() {
+        let query = encode_query(0x1234, "scanner.example.net.", TYPE_A).unwrap();
+        assert_eq!(
+            read_name(&query, 12).unwrap(),
+            ("scanner.example.net".to_string(), 33)
+        );
+        assert!(encode_query(1, "bad..name", TYPE_A).is_err());
+
+        // PTR answer, its name compressed against the question
+        let mut res = encode_query(7, "1.2.0.192.in-addr.arpa", TYPE_PTR).unwrap();
+        res[2] = 0x81;
+        res[7] = 1;
+        res.extend([0xc0, 12, 0, 12, 0, 1, 0, 0, 0, 60, 0, 7]);
+        res.extend([4, b'h', b'o', b's', b't', 0xc0, 16]);
+        let query = encode_query(7, "1.2.0.192.in-addr.arpa", TYPE_PTR).unwrap();
+        let records = decode_response(&res, &query).unwrap();
+        assert_eq!(
+            records,
+            [Record {
+                rtype: TYPE_PTR,
+                ttl: 60,
+                data: Rdata::Name("host.0.192.in-addr.arpa".to_string()),
+            }]
+        );
+
+        let other_id = encode_query(8, "1.2.0.192.in-addr.arpa", TYPE_PTR).unwrap();
+        assert!(decode_response(&res, &other_id).is_err());
+        let other_name = encode_query(7, "2.2.0.192.in-addr.arpa", TYPE_PTR).unwrap();
+        assert!(decode_response(&res, &other_name).is_err());
+        assert!(decode_response(&res[..20], &query).is_err());
+
+        // pointer to itself
+        let query = encode_query(7, "a", TYPE_PTR).unwrap();
+        let mut looped = query.clone();
+        looped[7] = 1;
+        looped.extend([0xc0, 19]);
+        looped[2] = 0x81;
+        assert!(decode_response(&looped, &query).is_err());
+    }
+
+    #[test]
+    fn lookups() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = socket.local_addr().unwrap();
+        // only the name missing from the hosts file is queried, and only once
+        let handle = std::thread::spawn(move || serve(socket, 1));
+
+        let dir = TempDir::new("hosts");
+        let hosts = dir.0.join("hosts");
+        std::fs::write(&hosts, "192.0.2.7 local.example.net\n").unwrap();
+        let resolver = Resolver::new(Some(&hosts), Some(server), Resolution::Blocking).unwrap();
+
+        let ip = resolver.lookup("local.example.net", 0).unwrap();
+        assert_eq!(ip, Some("192.0.2.7".parse().unwrap()));
+
+        let ip = resolver.lookup("Scanner.example.net.", 0).unwrap();
+        assert_eq!(ip, Some("192.0.2.1".parse().unwrap()));
+        handle.join().unwrap();
+
+        // cached for the ttl
+        let ip = resolver.lookup("scanner.example.net", 3599).unwrap();
+        assert_eq!(ip, Some("192.0.2.1".parse().unwrap()));
+
+        let resolver = Resolver::new(None, None, Resolution::Blocking).unwrap();
+        assert_eq!(resolver.lookup("scanner.example.net", 0).unwrap(), None);
+    }
+
+    #[test]
+    fn failures() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = socket.local_addr().unwrap();
+        // queried once, errors are cached like unknown names
+        let handle = std::thread::spawn(move || serve(socket, 1));
+
+        let resolver = Resolver::new(None, Some(server), Resolution::Blocking).unwrap();
+        assert_eq!(resolver.lookup("fail.example.net", 0).unwrap(), None);
+        handle.join().unwrap();
+        assert_eq!(
+            resolver
+                .lookup("fail.example.net", NEGATIVE_TTL - 1)
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn background() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = socket.local_addr().unwrap();
+        let handle = std::thread::spawn(move || serve(socket, 1));
+
+        // lines are skipped while resolving, not waited on
+        let resolver = Resolver::new(None, Some(server), Resolution::Background).unwrap();
+        assert!(is_pending(
+            &resolver.lookup("scanner.example.net", 0).unwrap_err()
+        ));
+        assert!(is_pending(
+            &resolver.lookup("scanner.example.net", 0).unwrap_err()
+        ));
+
+        let mut ip = None;
+        for _ in 0..40 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            if let Ok(resolved) = resolver.lookup("scanner.example.net", 0) {
+                ip = resolved;
+                break;
+            }
+        }
+        assert_eq!(ip, Some("192.0.2.1".parse().unwrap()));
+        handle.join().unwrap();
+    }
+}
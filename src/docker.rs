@@ -0,0 +1,413 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::result::Result::Ok;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::*;
+use tokio::sync::mpsc::Sender;
+use tokio::task::JoinHandle;
+
+use crate::follow::{Follower, POLL_INTERVAL};
+use crate::input::Entry;
+use crate::parsers::Kind;
+use crate::timestamp;
+use crate::utils::log;
+
+const SCAN_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Selector {
+    Name(String),
+    Label(String, Option<String>),
+}
+
+/// Containers to parse the logs of, e.g. json=caddy or json=label:app=web
+#[derive(Clone, Debug)]
+pub struct DockerSource {
+    pub kind: Kind,
+    pub selector: Selector,
+}
+
+pub fn parse_source(a: &str) -> Result<DockerSource> {
+    let (kind, selector) = a
+        .split_once('=')
+        .filter(|(_, s)| !s.is_empty())
+        .ok_or_else(|| {
+            anyhow!(
+                "invalid docker source {:?}, expected parser=name or parser=label:key[=value]",
+                a
+            )
+        })?;
+
+    let selector = match selector.strip_prefix("label:") {
+        Some(label) => match label.split_once('=') {
+            Some((k, v)) => Selector::Label(k.to_string(), Some(v.to_string())),
+            None => Selector::Label(label.to_string(), None),
+        },
+        None => Selector::Name(selector.trim_start_matches('/').to_string()),
+    };
+
+    Ok(DockerSource {
+        kind: Kind::from_str(kind)?,
+        selector,
+    })
+}
+
+impl Selector {
+    // against a container config.v2.json
+    fn matches(&self, config: &serde_json::Value) -> bool {
+        match self {
+            Selector::Name(name) => config
+                .get("Name")
+                .and_then(|n| n.as_str())
+                .is_some_and(|n| n.trim_start_matches('/') == name),
+            Selector::Label(key, value) => {
+                let label = config
+                    .get("Config")
+                    .and_then(|c| c.get("Labels"))
+                    .and_then(|l| l.get(key.as_str()))
+                    .and_then(|l| l.as_str());
+                match (label, value) {
+                    (Some(l), Some(v)) => l == v,
+                    (Some(_), None) => true,
+                    _ => false,
+                }
+            }
+        }
+    }
+}
+
+// reassembled lines past this are skipped, as a container never logging a newline would
+// grow its buffer forever
+const MAX_LINE: usize = 256 * 1024;
+
+// a line being reassembled, one per stream as stdout and stderr pieces interleave
+#[derive(Default)]
+struct Partial {
+    line: String,
+    overflow: bool,
+}
+
+// docker splits lines over 16k in several entries, only the last one ends with a newline
+fn unwrap(
+    line: &str,
+    partials: &mut HashMap<String, Partial>,
+) -> Result<Option<(String, Option<u64>)>> {
+    let json: serde_json::Value = serde_json::from_str(line)?;
+    let log = json
+        .get("log")
+        .and_then(|l| l.as_str())
+        .ok_or_else(|| anyhow!("cant parse docker log line - log"))?;
+    let stream = json
+        .get("stream")
+        .and_then(|s| s.as_str())
+        .unwrap_or("stdout");
+
+    let partial = partials.entry(stream.to_string()).or_default();
+    if !partial.overflow {
+        partial.line.push_str(log);
+    }
+    if partial.line.len() > MAX_LINE {
+        partial.line.clear();
+        partial.overflow = true;
+    }
+    if !log.ends_with('\n') {
+        return Ok(None);
+    }
+    if partial.overflow {
+        partial.overflow = false;
+        bail!("docker log line on {} too long, skipped", stream);
+    }
+
+    let line = partial.line.trim_end_matches(['\n', '\r']).to_string();
+    partial.line.clear();
+    let ts = json
+        .get("time")
+        .and_then(|t| t.as_str())
+        .and_then(timestamp::rfc3339);
+    Ok(Some((line, ts)))
+}
+
+async fn follow_file(kind: Kind, path: PathBuf, from_start: bool, tx: Sender<Entry>) -> Result<()> {
+    let mut follower = Follower::new(path, from_start).await;
+    let mut partials = HashMap::new();
+
+    loop {
+        for line in follower.poll().await {
+            match unwrap(&line, &mut partials) {
+                Ok(Some((line, ts))) => {
+                    tx.send(Entry {
+                        kind,
+                        line,
+                        ts,
+                        cursor: None,
+                    })
+                    .await?
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    log!("ERR: {:?}", e);
+                }
+            }
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+// container id and parser of the matching containers
+fn scan(root: &Path, sources: &[DockerSource]) -> Result<Vec<(String, Kind)>> {
+    let mut found = vec![];
+    for entry in std::fs::read_dir(root).with_context(|| format!("cant read {:?}", root))? {
+        // removed mid-scan, like those without a readable config
+        let dir = match entry {
+            Ok(entry) => entry.path(),
+            Err(_) => continue,
+        };
+        let config = match std::fs::read_to_string(dir.join("config.v2.json")) {
+            Ok(config) => config,
+            Err(_) => continue,
+        };
+        let config: serde_json::Value = match serde_json::from_str(&config) {
+            Ok(config) => config,
+            Err(_) => continue,
+        };
+
+        let id = dir
+            .file_name()
+            .and_then(|n| n.to_str())
+            .map(|n| n.to_string());
+        let kind = sources
+            .iter()
+            .find(|s| s.selector.matches(&config))
+            .map(|s| s.kind);
+        if let (Some(id), Some(kind)) = (id, kind) {
+            found.push((id, kind));
+        }
+    }
+    Ok(found)
+}
+
+/// Follows the logs of matching containers, picking up the ones (re)created later on
+pub async fn follow(sources: Vec<DockerSource>, root: PathBuf, tx: Sender<Entry>) -> Result<()> {
+    let mut followed: HashMap<String, JoinHandle<()>> = HashMap::new();
+    let mut first_scan = true;
+
+    loop {
+        // retried on the next scan, the containers followed so far still are
+        let found = match scan(&root, &sources) {
+            Ok(found) => found,
+            Err(e) => {
+                log!("ERR: scanning docker containers {:?}", e);
+                tokio::time::sleep(SCAN_INTERVAL).await;
+                continue;
+            }
+        };
+
+        // removed containers
+        followed.retain(|id, handle| {
+            let keep = found.iter().any(|(i, _)| i == id);
+            if !keep {
+                handle.abort();
+            }
+            keep
+        });
+
+        for (id, kind) in found {
+            if followed.contains_key(&id) {
+                continue;
+            }
+            let path = root.join(&id).join(format!("{}-json.log", id));
+            if !path.exists() {
+                continue;
+            }
+
+            // containers created after starting are read from their first line
+            log!(
+                "starting with {} parsing on container {}",
+                kind,
+                &id[..12.min(id.len())]
+            );
+            let tx = tx.clone();
+            let from_start = !first_scan;
+            let handle = tokio::spawn(async move {
+                if let Err(e) = follow_file(kind, path, from_start, tx).await {
+                    log!("ERR: following container logs {:?}", e);
+                }
+            });
+            followed.insert(id, handle);
+        }
+
+        first_scan = false;
+        tokio::time::sleep(SCAN_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+    use std::io::Write;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn sources() {
+        let s = parse_source("json=caddy").unwrap();
+        assert_eq!(s.kind, Kind::Json);
+        assert_eq!(s.selector, Selector::Name("caddy".to_string()));
+
+        let s = parse_source("clf=label:com.example.app=web").unwrap();
+        assert_eq!(
+            s.selector,
+            Selector::Label("com.example.app".to_string(), Some("web".to_string()))
+        );
+
+        let s = parse_source("clf=label:web").unwrap();
+        assert_eq!(s.selector, Selector::Label("web".to_string(), None));
+
+        assert!(parse_source("json=").is_err());
+        assert!(parse_source("nope=caddy").is_err());
+    }
+
+    #[test]
+    fn selectors() {
+        let config: serde_json::Value = serde_json::from_str(
+            r#"{"Name":"/caddy","Config":{"Labels":{"com.example.app":"web","proxy":""}}}"#,
+        )
+        .unwrap();
+
+        assert!(parse_source("json=caddy")
+            .unwrap()
+            .selector
+            .matches(&config));
+        assert!(!parse_source("json=nginx")
+            .unwrap()
+            .selector
+            .matches(&config));
+        assert!(parse_source("json=label:com.example.app=web")
+            .unwrap()
+            .selector
+            .matches(&config));
+        assert!(!parse_source("json=label:com.example.app=db")
+            .unwrap()
+            .selector
+            .matches(&config));
+        assert!(parse_source("json=label:proxy")
+            .unwrap()
+            .selector
+            .matches(&config));
+    }
+
+    #[test]
+    fn unwrapped() {
+        let mut partials = HashMap::new();
+        let line = r#"{"log":"8.8.8.8 - p [25/Sep/2021:13:49:56 +0200] \"POST /some/rpc HTTP/2.0\" 401 923\n","stream":"stdout","time":"2021-09-25T11:49:56.123456789Z"}"#;
+        let (line, ts) = unwrap(line, &mut partials).unwrap().unwrap();
+        assert_eq!(
+            line,
+            "8.8.8.8 - p [25/Sep/2021:13:49:56 +0200] \"POST /some/rpc HTTP/2.0\" 401 923"
+        );
+        assert_eq!(ts, Some(1632570596));
+
+        // long lines are split
+        assert!(unwrap(
+            r#"{"log":"abc","time":"2021-09-25T11:49:56Z"}"#,
+            &mut partials
+        )
+        .unwrap()
+        .is_none());
+        let (line, _) = unwrap(
+            r#"{"log":"def\n","time":"2021-09-25T11:49:56Z"}"#,
+            &mut partials,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(line, "abcdef");
+
+        assert!(unwrap(r#"{"stream":"stdout"}"#, &mut partials).is_err());
+
+        // interleaved streams aren't spliced together
+        let piece =
+            |log: &str, stream: &str| format!(r#"{{"log":"{}","stream":"{}"}}"#, log, stream);
+        assert!(unwrap(&piece("out", "stdout"), &mut partials)
+            .unwrap()
+            .is_none());
+        assert!(unwrap(&piece("err", "stderr"), &mut partials)
+            .unwrap()
+            .is_none());
+        let (line, _) = unwrap(&piece("put\\n", "stdout"), &mut partials)
+            .unwrap()
+            .unwrap();
+        assert_eq!(line, "output");
+        let (line, _) = unwrap(&piece("or\\n", "stderr"), &mut partials)
+            .unwrap()
+            .unwrap();
+        assert_eq!(line, "error");
+
+        // endless lines are dropped, up to their end
+        let long = "a".repeat(16 * 1024);
+        for _ in 0..(MAX_LINE / long.len() + 1) {
+            assert!(unwrap(&piece(&long, "stdout"), &mut partials)
+                .unwrap()
+                .is_none());
+        }
+        assert!(partials["stdout"].line.is_empty());
+        assert!(unwrap(&piece("end\\n", "stdout"), &mut partials).is_err());
+        let (line, _) = unwrap(&piece("next\\n", "stdout"), &mut partials)
+            .unwrap()
+            .unwrap();
+        assert_eq!(line, "next");
+    }
+
+    #[tokio::test]
+    async fn containers() {
+        let dir = TempDir::new("docker");
+        let root = dir.0.clone();
+        let id = "4f66ad9a0b2e6c3a0fa0b1b2c3d4e5f60718293a4b5c6d7e8f9a0b1c2d3e4f5a";
+        std::fs::create_dir_all(root.join(id)).unwrap();
+        std::fs::write(root.join(id).join("config.v2.json"), r#"{"Name":"/web"}"#).unwrap();
+        let log_path = root.join(id).join(format!("{}-json.log", id));
+        let mut log_file = std::fs::File::create(&log_path).unwrap();
+        writeln!(
+            log_file,
+            r#"{{"log":"old\n","time":"2021-09-25T11:49:56Z"}}"#
+        )
+        .unwrap();
+
+        let sources = vec![
+            parse_source("json=web").unwrap(),
+            parse_source("clf=db").unwrap(),
+        ];
+        assert_eq!(
+            scan(&root, &sources).unwrap(),
+            vec![(id.to_string(), Kind::Json)]
+        );
+
+        // existing lines are skipped, like tailed files
+        let (tx, mut rx) = mpsc::channel(8);
+        let handle = tokio::spawn(follow_file(Kind::Json, log_path.clone(), false, tx));
+        tokio::time::sleep(POLL_INTERVAL).await;
+        writeln!(
+            log_file,
+            r#"{{"log":"new\n","time":"2021-09-25T11:49:56Z"}}"#
+        )
+        .unwrap();
+        assert_eq!(rx.recv().await.unwrap().line, "new");
+
+        // truncated
+        log_file.set_len(0).unwrap();
+        tokio::time::sleep(POLL_INTERVAL * 2).await;
+        let mut log_file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(&log_path)
+            .unwrap();
+        writeln!(
+            log_file,
+            r#"{{"log":"after\n","time":"2021-09-25T11:49:56Z"}}"#
+        )
+        .unwrap();
+        assert_eq!(rx.recv().await.unwrap().line, "after");
+
+        handle.abort();
+    }
+}
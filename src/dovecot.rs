@@ -0,0 +1,92 @@
+use anyhow::*;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{net::IpAddr, str::FromStr};
+
+use crate::timestamp;
+use crate::utils::{Offence, ParsingStatus};
+
+lazy_static! {
+    static ref RE_RIP: Regex = Regex::new(r"\brip=([0-9a-fA-F:.]+)").unwrap();
+    static ref RE_USER: Regex = Regex::new(r"\buser=<([^>]*)>").unwrap();
+}
+
+// login processes log "Disconnected (auth failed, 1 attempts..." or "Aborted login (auth failed, 3 attempts..."
+pub fn parse(line: &str) -> Result<ParsingStatus> {
+    if !line.contains("auth failed") {
+        return Ok(ParsingStatus::OkEntry);
+    }
+
+    let ip = RE_RIP
+        .captures(line)
+        .and_then(|c| c.get(1))
+        .and_then(|m| IpAddr::from_str(m.as_str()).ok())
+        .ok_or_else(|| anyhow!("cant parse dovecot line"))?;
+    let user = RE_USER
+        .captures(line)
+        .and_then(|c| c.get(1))
+        .map(|u| u.as_str().to_string())
+        .filter(|u| !u.is_empty());
+
+    Ok(ParsingStatus::BadEntry(
+        Offence::new(ip, timestamp::syslog(line)).with_user(user),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positive() {
+        let vectors = [
+            "Sep 26 06:25:19 mail dovecot: imap-login: Disconnected (auth failed, 1 attempts in 2 secs): user=<neal>, method=PLAIN, rip=179.124.36.195, lip=10.0.0.2, TLS, session=<c1W8dQ7M>",
+            "Sep 26 06:25:20 mail dovecot: pop3-login: Aborted login (auth failed, 3 attempts in 12 secs): user=<>, method=LOGIN, rip=2001:db8::1, lip=2001:db8::2, session=<abc>",
+            "2021-09-26T06:25:21.123456+02:00 mail dovecot: submission-login: Disconnected: Connection closed (auth failed, 1 attempts in 4 secs): user=<admin@example.org>, method=PLAIN, rip=35.184.211.144, lip=10.0.0.2",
+        ];
+
+        vectors.iter().for_each(|e| {
+            let ret = parse(e).unwrap();
+            match ret {
+                ParsingStatus::BadEntry(_) => {}
+                _ => panic!("bad parsing"),
+            }
+        });
+
+        match parse(vectors[0]).unwrap() {
+            ParsingStatus::BadEntry(o) => assert_eq!(o.user.as_deref(), Some("neal")),
+            _ => panic!("bad parsing"),
+        }
+        match parse(vectors[1]).unwrap() {
+            ParsingStatus::BadEntry(o) => assert_eq!(o.user, None),
+            _ => panic!("bad parsing"),
+        }
+    }
+
+    #[test]
+    fn negative() {
+        let vectors = [
+            "Sep 26 06:25:19 mail dovecot: imap-login: Login: user=<neal>, method=PLAIN, rip=179.124.36.195, lip=10.0.0.2, mpid=4242, TLS",
+            "Sep 26 06:25:20 mail dovecot: imap-login: Disconnected (no auth attempts in 0 secs): user=<>, rip=35.184.211.144, lip=10.0.0.2",
+        ];
+
+        vectors.iter().for_each(|e| {
+            let ret = parse(e).unwrap();
+            match ret {
+                ParsingStatus::OkEntry => {}
+                _ => panic!("bad parsing"),
+            }
+        })
+    }
+
+    #[test]
+    fn malformed() {
+        let vectors = [
+            "Sep 26 06:25:19 mail dovecot: imap-login: Disconnected (auth failed, 1 attempts in 2 secs): user=<neal>, method=PLAIN, lip=10.0.0.2",
+        ];
+
+        vectors.iter().for_each(|e| {
+            parse(e).expect_err("");
+        })
+    }
+}
@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::result::Result::Ok;
+
+use anyhow::*;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+
+use crate::utils::log;
+
+// includes of includes
+const MAX_DEPTH: usize = 8;
+
+// nested %(var)s and <var> substitutions
+const MAX_PASSES: usize = 16;
+
+lazy_static! {
+    static ref RE_SECTION: Regex = Regex::new(r"^\[([^\]]+)\]\s*$").unwrap();
+    static ref RE_KEY: Regex = Regex::new(r"^([^=:\s][^=:]*?)\s*[=:]\s?(.*)$").unwrap();
+    static ref RE_INTERPOLATION: Regex = Regex::new(r"%\(([\w/]+)\)s").unwrap();
+    static ref RE_TAG: Regex = Regex::new(r"<([\w\-/]+)>").unwrap();
+    static ref RE_FIELD_TAG: Regex = Regex::new(r"<(/?)F-([\w\-]+)>").unwrap();
+    static ref RE_GROUP_NAME: Regex = Regex::new(r"\(\?P<(ip|user)>").unwrap();
+}
+
+// python regex constructs the regex crate doesn't support
+const UNSUPPORTED: [&str; 5] = ["(?=", "(?!", "(?<=", "(?<!", "(?P="];
+
+/// failregex and ignoreregex of a filter.d file, converted to the generic parser's regexes
+#[derive(Debug, Default)]
+pub struct Filter {
+    pub failregex: Vec<String>,
+    pub ignoreregex: Vec<String>,
+}
+
+// section -> key -> value
+type Config = HashMap<String, HashMap<String, String>>;
+
+// ini entries in order, values continued on indented lines
+fn parse_ini(content: &str) -> Vec<(String, String, String)> {
+    let mut entries: Vec<(String, String, String)> = vec![];
+    let mut section = String::from("DEFAULT");
+
+    for line in content.lines() {
+        let trimmed = line.trim();
+        if line.starts_with([' ', '\t']) && !trimmed.is_empty() {
+            if let Some((_, _, value)) = entries.last_mut() {
+                if !trimmed.starts_with(['#', ';']) {
+                    value.push('\n');
+                    value.push_str(trimmed);
+                }
+                continue;
+            }
+        }
+        if trimmed.is_empty() || trimmed.starts_with(['#', ';']) {
+            continue;
+        }
+
+        if let Some(c) = RE_SECTION.captures(trimmed) {
+            section = c[1].trim().to_string();
+        } else if let Some(c) = RE_KEY.captures(trimmed) {
+            entries.push((section.clone(), c[1].to_string(), c[2].trim().to_string()));
+        }
+    }
+    entries
+}
+
+// a value overriding an included one can refer to it as %(known/key)s
+fn set(config: &mut Config, section: &str, key: &str, value: &str) {
+    let values = config.entry(section.to_string()).or_default();
+    let known = values.get(key).cloned().unwrap_or_default();
+    let value = value.replace(&format!("%(known/{})s", key), &known);
+    values.insert(key.to_string(), value);
+}
+
+fn read(path: &Path, config: &mut Config, depth: usize) -> Result<()> {
+    ensure!(
+        depth < MAX_DEPTH,
+        "fail2ban includes too deep at {:?}",
+        path
+    );
+    let content = std::fs::read_to_string(path).with_context(|| format!("cant read {:?}", path))?;
+    let entries = parse_ini(&content);
+    let dir = path.parent().unwrap_or(Path::new("."));
+
+    let includes = |name: &str| -> Vec<PathBuf> {
+        entries
+            .iter()
+            .filter(|(s, k, _)| s == "INCLUDES" && k == name)
+            .flat_map(|(_, _, v)| v.split_whitespace())
+            .map(|f| dir.join(f))
+            .collect()
+    };
+    let (before, after) = (includes("before"), includes("after"));
+
+    for p in before {
+        read(&p, config, depth + 1)?;
+    }
+    for (section, key, value) in &entries {
+        set(config, section, key, value);
+    }
+
+    // fail2ban reads the .local next to a .conf, then the after includes, which may not exist
+    let local = path.with_extension("local");
+    if path.extension().is_some_and(|e| e == "conf") && local.exists() {
+        read(&local, config, depth + 1)?;
+    }
+    for p in after.iter().filter(|p| p.exists() && **p != local) {
+        read(p, config, depth + 1)?;
+    }
+    Ok(())
+}
+
+// %(var)s and <var> substitutions, fail2ban tags like <HOST> are left as is
+fn interpolate(value: &str, vars: &HashMap<String, String>) -> Result<String> {
+    let mut value = value.to_string();
+    for _ in 0..MAX_PASSES {
+        let next = RE_INTERPOLATION.replace_all(&value, |c: &Captures| {
+            vars.get(&c[1]).cloned().unwrap_or_default()
+        });
+        let next = RE_TAG.replace_all(&next, |c: &Captures| {
+            // python named groups, (?P<name>...)
+            let start = c.get(0).map(|m| m.start()).unwrap_or(0);
+            match vars.get(&c[1]) {
+                Some(v) if !next[..start].ends_with("?P") => v.clone(),
+                _ => c[0].to_string(),
+            }
+        });
+        if next == value {
+            return Ok(value);
+        }
+        value = next.to_string();
+    }
+    bail!("fail2ban variables nested too deep in {:?}", value)
+}
+
+// fail2ban tags to named groups, and python specifics to the regex crate syntax
+fn convert(regex: &str) -> Result<String> {
+    if regex.contains("<SKIPLINES>") || regex.contains("<F-NOFAIL>") {
+        bail!("multi-line and nofail regexes are not supported");
+    }
+    if let Some(u) = UNSUPPORTED.iter().find(|u| regex.contains(*u)) {
+        bail!("{} lookarounds and backreferences are not supported", u);
+    }
+
+    let host = r"(?:::f{4,6}:)?(?P<ip>[\w\-.^_]*\w)";
+    let regex = regex
+        .replace("<HOST>", host)
+        .replace("<ADDR>", r"(?:::f{4,6}:)?(?P<ip>[0-9a-fA-F:.]*[0-9a-fA-F])")
+        .replace("<IP4>", r"(?P<ip>\d{1,3}\.\d{1,3}\.\d{1,3}\.\d{1,3})")
+        .replace("<IP6>", r"(?P<ip>[0-9a-fA-F:]{2,})")
+        .replace("<DNS>", r"(?P<ip>[\w\-.^_]*\w)")
+        .replace(r"\Z", r"\z");
+
+    // user fields are kept, the others only group
+    let regex = RE_FIELD_TAG.replace_all(&regex, |c: &Captures| match (&c[1], &c[2]) {
+        ("/", _) => ")".to_string(),
+        (_, "USER") | (_, "ALT_USER") => "(?P<user>".to_string(),
+        _ => "(?:".to_string(),
+    });
+
+    // a field captured in several branches, the parser checks the numbered ones too
+    let mut seen: HashMap<String, u32> = HashMap::new();
+    let regex = RE_GROUP_NAME.replace_all(&regex, |c: &Captures| {
+        let n = seen.entry(c[1].to_string()).or_insert(0);
+        *n += 1;
+        match *n {
+            1 => c[0].to_string(),
+            n => format!("(?P<{}_{}>", &c[1], n - 1),
+        }
+    });
+
+    ensure!(
+        regex.contains("(?P<ip"),
+        "no <HOST>, <ADDR>, <IP4>, <IP6> or <DNS> tag"
+    );
+    Regex::new(&regex)?;
+    Ok(regex.to_string())
+}
+
+/// Loads a fail2ban filter.d file, with its includes and variables. Regexes that can't be
+/// converted are skipped with a warning.
+pub fn load(path: &Path) -> Result<Filter> {
+    let mut config = Config::new();
+    read(path, &mut config, 0)?;
+
+    // [Init] values override the [Definition] ones, like fail2ban's jail parameters
+    let mut vars = HashMap::new();
+    for section in ["DEFAULT", "Definition", "Init"] {
+        if let Some(values) = config.get(section) {
+            vars.extend(values.clone());
+        }
+    }
+
+    let regexes = |key: &str| -> Result<Vec<String>> {
+        let value = match vars.get(key) {
+            Some(value) => interpolate(value, &vars)?,
+            None => return Ok(vec![]),
+        };
+
+        let mut converted = vec![];
+        for regex in value.lines().map(|r| r.trim()).filter(|r| !r.is_empty()) {
+            match convert(regex) {
+                Ok(regex) => converted.push(regex),
+                Err(e) if key == "failregex" => {
+                    log!("WARN: skipped {} {:?} of {:?}, {}", key, regex, path, e);
+                }
+                // ignoreregex have no host, only validated
+                Err(_) => {
+                    let regex = regex.replace(r"\Z", r"\z");
+                    Regex::new(&regex)
+                        .with_context(|| format!("invalid ignoreregex in {:?}", path))?;
+                    converted.push(regex);
+                }
+            }
+        }
+        Ok(converted)
+    };
+
+    let filter = Filter {
+        failregex: regexes("failregex")?,
+        ignoreregex: regexes("ignoreregex")?,
+    };
+    ensure!(
+        !filter.failregex.is_empty(),
+        "no usable failregex in {:?}",
+        path
+    );
+    Ok(filter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+
+    const COMMON: &str = r#"
+[INCLUDES]
+after = common.local
+
+[DEFAULT]
+__pid_re = (?:\[\d+\])
+__daemon_re = [\[\(]?%(_daemon)s(?:\(\S+\))?[\]\)]?:?
+__prefix_line = \s*(?:\S+ )?%(__daemon_re)s%(__pid_re)s?:?\s+
+"#;
+
+    const SSHD: &str = r#"
+# a filter in the style of fail2ban's sshd.conf
+[INCLUDES]
+before = common.conf
+
+[Definition]
+_daemon = sshd
+
+failregex = ^%(__prefix_line)sFailed (?:password|publickey) for (?:invalid user )?<F-USER>\S+</F-USER> from <HOST>(?: port \d+)?(?: ssh\d*)?\s*$
+            ^%(__prefix_line)sInvalid user <F-USER>\S*</F-USER> from <HOST>
+            ; a comment in the middle
+            ^%(__prefix_line)s(?:error: )?maximum authentication attempts exceeded for .* from <HOST>(?=\s|$)
+
+ignoreregex = for backup from
+
+[Init]
+maxlines = 1
+"#;
+
+    #[test]
+    fn ini() {
+        let entries = parse_ini("a = 1\n[S]\nb: 2\n  3\n# c = 4\n; d = 5\ne =\n");
+        assert_eq!(
+            entries,
+            [
+                ("DEFAULT".to_string(), "a".to_string(), "1".to_string()),
+                ("S".to_string(), "b".to_string(), "2\n3".to_string()),
+                ("S".to_string(), "e".to_string(), "".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn conversions() {
+        let re =
+            convert(r"^<F-USER>\S+</F-USER> from <HOST>|^<F-ALT_USER>\S+</F-ALT_USER> at <ADDR>\Z")
+                .unwrap();
+        assert_eq!(re.matches("(?P<ip").count(), 2);
+        assert!(re.contains("(?P<user_1>"));
+        assert!(re.ends_with(r"\z"));
+
+        let re = Regex::new(&convert(r"client <F-ID>\d+</F-ID> from <HOST>").unwrap()).unwrap();
+        let c = re.captures("client 12 from ::ffff:192.0.2.7").unwrap();
+        assert_eq!(&c["ip"], "192.0.2.7");
+
+        assert!(convert(r"from <HOST>(?=\s)").is_err());
+        assert!(convert(r"^<F-NOFAIL>from <HOST>").is_err());
+        assert!(convert(r"no host here").is_err());
+    }
+
+    #[test]
+    fn filters() {
+        let dir = TempDir::new("fail2ban");
+        std::fs::write(dir.0.join("common.conf"), COMMON).unwrap();
+        std::fs::write(dir.0.join("sshd.conf"), SSHD).unwrap();
+        std::fs::write(
+            dir.0.join("sshd.local"),
+            "[Definition]\nignoreregex = %(known/ignoreregex)s\n  for deploy from\n",
+        )
+        .unwrap();
+
+        let filter = load(&dir.0.join("sshd.conf")).unwrap();
+
+        // the lookahead one is skipped
+        assert_eq!(filter.failregex.len(), 2);
+        assert_eq!(filter.ignoreregex, ["for backup from", "for deploy from"]);
+
+        let re = Regex::new(&filter.failregex[0]).unwrap();
+        let c = re
+            .captures("livecompute sshd[23246]: Failed password for root from 179.124.36.195 port 41883 ssh2")
+            .unwrap();
+        assert_eq!(&c["ip"], "179.124.36.195");
+        assert_eq!(&c["user"], "root");
+        assert!(!re.is_match("livecompute cron[1]: Failed password for root from 179.124.36.195"));
+
+        assert!(load(&dir.0.join("missing.conf")).is_err());
+
+        std::fs::write(dir.0.join("empty.conf"), "[Definition]\nfailregex =\n").unwrap();
+        assert!(load(&dir.0.join("empty.conf")).is_err());
+    }
+}
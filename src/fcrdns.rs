@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::result::Result::Ok;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::*;
+
+use crate::dns::{self, Rdata, NEGATIVE_TTL, TYPE_A, TYPE_AAAA, TYPE_PTR};
+use crate::utils::log;
+
+// prune expired entries past this many cached ips
+const MAX_CACHE: usize = 10_000;
+
+/// Name to query for the PTR records of an ip
+fn reverse_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let o = ip.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", o[3], o[2], o[1], o[0])
+        }
+        IpAddr::V6(ip) => {
+            let mut labels: Vec<String> = ip
+                .octets()
+                .iter()
+                .flat_map(|b| [b >> 4, b & 0xf])
+                .map(|n| format!("{:x}", n))
+                .collect();
+            labels.reverse();
+            format!("{}.ip6.arpa", labels.join("."))
+        }
+    }
+}
+
+enum Cached {
+    Verified(bool, u64),
+    Pending,
+}
+
+type Cache = Arc<Mutex<HashMap<IpAddr, Cached>>>;
+
+fn is_allowed_name(domains: &[String], name: &str) -> bool {
+    domains.iter().any(|d| {
+        name.strip_suffix(d.as_str())
+            .is_some_and(|prefix| prefix.ends_with('.'))
+    })
+}
+
+// whether it's verified, and for how long
+async fn verify(domains: &[String], server: SocketAddr, ip: IpAddr) -> Result<(bool, u64)> {
+    let qtype = if ip.is_ipv4() { TYPE_A } else { TYPE_AAAA };
+
+    for ptr in dns::query_async(server, &reverse_name(ip), TYPE_PTR).await? {
+        let name = match &ptr.data {
+            Rdata::Name(name) if is_allowed_name(domains, name) => name,
+            _ => continue,
+        };
+        let confirmed = dns::query_async(server, name, qtype)
+            .await?
+            .into_iter()
+            .find(|r| r.data == Rdata::Ip(ip));
+        if let Some(forward) = confirmed {
+            return Ok((true, ptr.ttl.min(forward.ttl) as u64));
+        }
+    }
+
+    Ok((false, NEGATIVE_TTL))
+}
+
+/// Forward-confirmed reverse DNS - the PTR name of an ip is in an allowed domain, and
+/// resolves back to the same ip. Good crawlers are verified this way. Checks run in the
+/// background, ips are unverified until they're done, or when they fail
+pub struct Verifier {
+    domains: Arc<Vec<String>>,
+    server: SocketAddr,
+    cache: Cache,
+    pruned: AtomicU64,
+}
+
+impl Verifier {
+    pub fn new(domains: &[String], server: SocketAddr) -> Verifier {
+        // *.googlebot.com and googlebot.com are the same rule
+        let domains = domains
+            .iter()
+            .map(|d| d.trim_start_matches("*.").trim_matches('.').to_lowercase())
+            .collect();
+
+        Verifier {
+            domains: Arc::new(domains),
+            server,
+            cache: Arc::new(Mutex::new(HashMap::new())),
+            pruned: AtomicU64::new(0),
+        }
+    }
+
+    pub fn is_allowed(&self, ip: IpAddr, now: u64) -> bool {
+        {
+            let mut cache = match self.cache.lock() {
+                Ok(cache) => cache,
+                Err(_) => return false,
+            };
+            match cache.get(&ip) {
+                Some(Cached::Verified(allowed, expires)) if *expires > now => return *allowed,
+                Some(Cached::Pending) => return false,
+                _ => {}
+            }
+
+            // at most once per negative ttl, not to rescan the cache on every offence
+            let pruned = self.pruned.load(Ordering::Relaxed);
+            if cache.len() > MAX_CACHE && now >= pruned + NEGATIVE_TTL {
+                self.pruned.store(now, Ordering::Relaxed);
+                cache.retain(|_, c| !matches!(c, Cached::Verified(_, expires) if *expires <= now));
+            }
+            cache.insert(ip, Cached::Pending);
+        }
+
+        let domains = self.domains.clone();
+        let server = self.server;
+        let cache = self.cache.clone();
+        tokio::spawn(async move {
+            // broken or unreachable dns doesn't get anyone allowed
+            let (allowed, ttl) = match verify(&domains, server, ip).await {
+                Ok(verified) => verified,
+                Err(e) => {
+                    log!("WARN: cant verify reverse dns of {}, {:?}", ip, e);
+                    (false, NEGATIVE_TTL)
+                }
+            };
+            if let Ok(mut cache) = cache.lock() {
+                cache.insert(ip, Cached::Verified(allowed, now + ttl));
+            }
+        });
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+
+    // answers PTR questions with `name`, and A questions with `ip`
+    fn serve(socket: UdpSocket, answers: usize, name: &str, ip: [u8; 4]) {
+        let mut buf = [0; 512];
+        for _ in 0..answers {
+            let (len, peer) = socket.recv_from(&mut buf).unwrap();
+            let mut res = buf[..len].to_vec();
+            let qtype = u16::from_be_bytes([res[len - 4], res[len - 3]]);
+            res[2] = 0x81;
+            res[3] = 0x80;
+            res[7] = 1;
+            if qtype == TYPE_PTR {
+                let mut rdata = vec![];
+                for label in name.split('.') {
+                    rdata.push(label.len() as u8);
+                    rdata.extend(label.as_bytes());
+                }
+                rdata.push(0);
+                res.extend([
+                    0xc0,
+                    12,
+                    0,
+                    12,
+                    0,
+                    1,
+                    0,
+                    0,
+                    0x0e,
+                    0x10,
+                    0,
+                    rdata.len() as u8,
+                ]);
+                res.extend(rdata);
+            } else {
+                res.extend([0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+                res.extend(ip);
+            }
+            socket.send_to(&res, peer).unwrap();
+        }
+    }
+
+    fn stub(
+        answers: usize,
+        name: &'static str,
+        ip: [u8; 4],
+    ) -> (SocketAddr, std::thread::JoinHandle<()>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = socket.local_addr().unwrap();
+        let handle = std::thread::spawn(move || serve(socket, answers, name, ip));
+        (server, handle)
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(
+            reverse_name("66.249.66.1".parse().unwrap()),
+            "1.66.249.66.in-addr.arpa"
+        );
+        assert_eq!(
+            reverse_name("2001:db8::1".parse().unwrap()),
+            "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+        );
+
+        let server = "127.0.0.1:53".parse().unwrap();
+        let verifier = Verifier::new(
+            &["*.googlebot.com".to_string(), "search.msn.com.".to_string()],
+            server,
+        );
+        let allowed = |name| is_allowed_name(&verifier.domains, name);
+        assert!(allowed("crawl-66-249-66-1.googlebot.com"));
+        assert!(allowed("msnbot-157-55-39-1.search.msn.com"));
+        assert!(!allowed("googlebot.com"));
+        assert!(!allowed("crawl.evil-googlebot.com"));
+        assert!(!allowed("googlebot.com.evil.net"));
+    }
+
+    fn domains() -> Vec<String> {
+        vec!["googlebot.com".to_string()]
+    }
+
+    #[tokio::test]
+    async fn confirmed() {
+        let (server, handle) = stub(2, "crawl-66-249-66-1.googlebot.com", [66, 249, 66, 1]);
+        let ip = "66.249.66.1".parse().unwrap();
+
+        // cached for the shortest ttl
+        assert_eq!(verify(&domains(), server, ip).await.unwrap(), (true, 60));
+        handle.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn spoofed() {
+        // the PTR of an ip one controls can say anything, the forward lookup can't
+        let (server, handle) = stub(2, "crawl-66-249-66-1.googlebot.com", [66, 249, 66, 1]);
+        let ip = "192.0.2.1".parse().unwrap();
+        assert_eq!(
+            verify(&domains(), server, ip).await.unwrap(),
+            (false, NEGATIVE_TTL)
+        );
+        handle.join().unwrap();
+
+        // not even looked up forward
+        let (server, handle) = stub(1, "crawl.evil-googlebot.com", [192, 0, 2, 1]);
+        assert_eq!(
+            verify(&domains(), server, ip).await.unwrap(),
+            (false, NEGATIVE_TTL)
+        );
+        handle.join().unwrap();
+    }
+
+    async fn settled(verifier: &Verifier, ip: IpAddr) {
+        for _ in 0..40 {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            let cache = verifier.cache.lock().unwrap();
+            if let Some(Cached::Verified(..)) = cache.get(&ip) {
+                return;
+            }
+        }
+        panic!("never verified");
+    }
+
+    #[tokio::test]
+    async fn background() {
+        let (server, handle) = stub(2, "crawl-66-249-66-1.googlebot.com", [66, 249, 66, 1]);
+        let verifier = Verifier::new(&domains(), server);
+        let ip = "66.249.66.1".parse().unwrap();
+
+        // sentenced as unverified until the answer comes
+        assert!(!verifier.is_allowed(ip, 0));
+        assert!(!verifier.is_allowed(ip, 0));
+        settled(&verifier, ip).await;
+        assert!(verifier.is_allowed(ip, 59));
+        handle.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn unreachable() {
+        // nothing listening there, errors leave ips unverified, and are cached
+        let server = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let verifier = Verifier::new(&domains(), server);
+        let ip = "66.249.66.1".parse().unwrap();
+
+        assert!(!verifier.is_allowed(ip, 0));
+        settled(&verifier, ip).await;
+        assert!(!verifier.is_allowed(ip, NEGATIVE_TTL - 1));
+    }
+}
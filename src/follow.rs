@@ -0,0 +1,334 @@
+use std::io::SeekFrom;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::result::Result::Ok;
+use std::time::Duration;
+
+use anyhow::*;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::utils::log;
+
+pub const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// polls a rotated file is still read for, as writers take a moment to reopen their logfile
+const ROTATED_GRACE: u32 = 20;
+
+struct Opened {
+    file: File,
+    ino: u64,
+    pos: u64,
+    pending: Vec<u8>,
+}
+
+impl Opened {
+    async fn open(path: &Path) -> Result<Opened> {
+        let file = File::open(path).await?;
+        let ino = file.metadata().await?.ino();
+        Ok(Opened {
+            file,
+            ino,
+            pos: 0,
+            pending: vec![],
+        })
+    }
+
+    // complete lines appended since the last read
+    async fn read(&mut self) -> Result<Vec<String>> {
+        self.file.seek(SeekFrom::Start(self.pos)).await?;
+        let before = self.pending.len();
+        let read = match self.file.read_to_end(&mut self.pending).await {
+            Ok(read) => read,
+            Err(e) => {
+                // read again from the same position on the next poll
+                self.pending.truncate(before);
+                return Err(e.into());
+            }
+        };
+        self.pos += read as u64;
+
+        let mut lines = vec![];
+        while let Some(i) = self.pending.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=i).collect();
+            let line = String::from_utf8_lossy(&line[..i]);
+            lines.push(line.trim_end_matches('\r').to_string());
+        }
+        Ok(lines)
+    }
+
+    // a last line without newline, once nothing more will be written
+    fn flush(&mut self) -> Option<String> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let line = String::from_utf8_lossy(&self.pending).to_string();
+        self.pending.clear();
+        Some(line)
+    }
+}
+
+/// Follows a logfile by its path, like `tail -F`. Survives the file being renamed,
+/// removed, truncated or not existing yet, and read errors, retried on the next poll.
+pub struct Follower {
+    path: PathBuf,
+    current: Option<Opened>,
+    rotated: Option<(Opened, u32)>,
+    // errors are logged once, until a poll goes through
+    failing: bool,
+}
+
+impl Follower {
+    /// Starts at the end of the file, unless `from_start`. A file created later on is read from its start.
+    pub async fn new(path: PathBuf, from_start: bool) -> Follower {
+        let mut current = Opened::open(&path).await.ok();
+        if let Some(opened) = current.as_mut().filter(|_| !from_start) {
+            opened.pos = opened.file.metadata().await.map(|m| m.len()).unwrap_or(0);
+        }
+
+        Follower {
+            path,
+            current,
+            rotated: None,
+            failing: false,
+        }
+    }
+
+    /// Where the following starts or is at, in the current file
+    pub fn position(&self) -> u64 {
+        self.current.as_ref().map(|c| c.pos).unwrap_or(0)
+    }
+
+    pub async fn poll(&mut self) -> Vec<String> {
+        let mut lines = vec![];
+        let mut errors = vec![];
+
+        // lines written to the rotated file until the writer reopened the new one
+        if let Some((rotated, polls)) = self.rotated.as_mut() {
+            match rotated.read().await {
+                Ok(read) => lines.extend(read),
+                Err(e) => errors.push(e.context("cant read rotated file")),
+            }
+            *polls += 1;
+            if *polls >= ROTATED_GRACE {
+                lines.extend(rotated.flush());
+                self.rotated = None;
+            }
+        }
+
+        if let Some(current) = self.current.as_mut() {
+            match current.read().await {
+                Ok(read) => lines.extend(read),
+                Err(e) => errors.push(e.context("cant read file")),
+            }
+        }
+
+        // removed or renamed, and not recreated yet
+        if let Ok(meta) = tokio::fs::metadata(&self.path).await {
+            let replaced = self.current.as_ref().map_or(true, |c| c.ino != meta.ino());
+            if replaced {
+                if let Some(previous) = self.current.take() {
+                    if let Some((mut older, _)) = self.rotated.take() {
+                        lines.extend(older.flush());
+                    }
+                    self.rotated = Some((previous, 0));
+                }
+
+                // gone again before it could be opened, e.g. mid logrotate, opened on the next poll
+                match Opened::open(&self.path).await {
+                    Ok(mut opened) => {
+                        match opened.read().await {
+                            Ok(read) => lines.extend(read),
+                            Err(e) => errors.push(e.context("cant read file")),
+                        }
+                        self.current = Some(opened);
+                    }
+                    Err(e) => errors.push(e.context("cant open file")),
+                }
+            } else if let Some(current) = self.current.as_mut() {
+                // truncated in place, e.g. logrotate's copytruncate. A file truncated and written
+                // past the previous position within a poll can't be told apart from appends.
+                if meta.len() < current.pos {
+                    current.pos = 0;
+                    current.pending.clear();
+                    match current.read().await {
+                        Ok(read) => lines.extend(read),
+                        Err(e) => errors.push(e.context("cant read file")),
+                    }
+                }
+            }
+        }
+
+        if !self.failing {
+            for e in &errors {
+                log!("ERR: following {:?}, retrying: {:?}", self.path, e);
+            }
+        }
+        self.failing = !errors.is_empty();
+
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+
+    fn append(path: &Path, data: &str) {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        file.write_all(data.as_bytes()).unwrap();
+    }
+
+    async fn poll_all(follower: &mut Follower, polls: u32) -> Vec<String> {
+        let mut lines = vec![];
+        for _ in 0..polls {
+            lines.extend(follower.poll().await);
+        }
+        lines
+    }
+
+    #[tokio::test]
+    async fn appended() {
+        let dir = TempDir::new("follow-appended");
+        let path = dir.0.join("auth.log");
+        append(&path, "before\n");
+
+        let mut follower = Follower::new(path.clone(), false).await;
+        assert_eq!(follower.position(), 7);
+        assert!(follower.poll().await.is_empty());
+
+        append(&path, "a\nb\npart");
+        assert_eq!(follower.poll().await, ["a", "b"]);
+        append(&path, "ial\r\n");
+        assert_eq!(follower.poll().await, ["partial"]);
+    }
+
+    #[tokio::test]
+    async fn from_start() {
+        let dir = TempDir::new("follow-from-start");
+        let path = dir.0.join("auth.log");
+        append(&path, "before\n");
+
+        let mut follower = Follower::new(path.clone(), true).await;
+        assert_eq!(follower.poll().await, ["before"]);
+    }
+
+    #[tokio::test]
+    async fn created_later() {
+        let dir = TempDir::new("follow-created");
+        let path = dir.0.join("auth.log");
+
+        let mut follower = Follower::new(path.clone(), false).await;
+        assert!(follower.poll().await.is_empty());
+
+        append(&path, "a\nb\n");
+        assert_eq!(follower.poll().await, ["a", "b"]);
+    }
+
+    // logrotate's default: rename, create a new file, the writer reopens it a bit later
+    #[tokio::test]
+    async fn renamed() {
+        let dir = TempDir::new("follow-renamed");
+        let path = dir.0.join("auth.log");
+        let rotated = dir.0.join("auth.log.1");
+        append(&path, "before\n");
+
+        let mut follower = Follower::new(path.clone(), false).await;
+        append(&path, "a\n");
+        assert_eq!(follower.poll().await, ["a"]);
+
+        append(&path, "b\n");
+        fs::rename(&path, &rotated).unwrap();
+        append(&rotated, "c\n");
+        append(&path, "d\n");
+        assert_eq!(follower.poll().await, ["b", "c", "d"]);
+
+        // still written to after the rotation, and left with a partial line
+        append(&rotated, "e\nf");
+        append(&path, "g\n");
+        let lines = poll_all(&mut follower, ROTATED_GRACE).await;
+        assert_eq!(lines, ["e", "g", "f"]);
+
+        append(&rotated, "lost\n");
+        append(&path, "h\n");
+        assert_eq!(follower.poll().await, ["h"]);
+    }
+
+    // logrotate's copytruncate: copy, then truncate in place
+    #[tokio::test]
+    async fn truncated() {
+        let dir = TempDir::new("follow-truncated");
+        let path = dir.0.join("auth.log");
+        append(&path, "before\n");
+
+        let mut follower = Follower::new(path.clone(), false).await;
+        append(&path, "a\nb\n");
+        assert_eq!(follower.poll().await, ["a", "b"]);
+
+        fs::copy(&path, dir.0.join("auth.log.1")).unwrap();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(0)
+            .unwrap();
+        assert!(follower.poll().await.is_empty());
+
+        append(&path, "c\nlonger line\n");
+        assert_eq!(follower.poll().await, ["c", "longer line"]);
+
+        // truncated and written to within the same poll
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(0)
+            .unwrap();
+        append(&path, "d\n");
+        assert_eq!(follower.poll().await, ["d"]);
+    }
+
+    // removed, then recreated
+    #[tokio::test]
+    async fn recreated() {
+        let dir = TempDir::new("follow-recreated");
+        let path = dir.0.join("auth.log");
+        append(&path, "before\n");
+
+        let mut follower = Follower::new(path.clone(), false).await;
+        append(&path, "a\n");
+        fs::remove_file(&path).unwrap();
+        assert_eq!(follower.poll().await, ["a"]);
+        assert!(follower.poll().await.is_empty());
+
+        append(&path, "b\n");
+        assert_eq!(follower.poll().await, ["b"]);
+        append(&path, "c\n");
+        assert_eq!(follower.poll().await, ["c"]);
+    }
+
+    // errors are retried on the next poll, here a directory in place of the file
+    #[tokio::test]
+    async fn unreadable() {
+        let dir = TempDir::new("follow-unreadable");
+        let path = dir.0.join("auth.log");
+        append(&path, "before\n");
+
+        let mut follower = Follower::new(path.clone(), false).await;
+        fs::remove_file(&path).unwrap();
+        fs::create_dir(&path).unwrap();
+        assert!(follower.poll().await.is_empty());
+        assert!(follower.poll().await.is_empty());
+
+        fs::remove_dir(&path).unwrap();
+        append(&path, "a\n");
+        assert_eq!(follower.poll().await, ["a"]);
+    }
+}
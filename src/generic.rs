@@ -1,41 +1,141 @@
-use crate::utils::ParsingStatus;
+use crate::dns::Resolver;
+use crate::fail2ban::Filter;
+use crate::timestamp;
+use crate::utils::{get_epoch, Offence, ParsingStatus};
 use anyhow::*;
-use regex::Regex;
+use regex::{Captures, Match, Regex, RegexSet};
+use std::result::Result::Ok;
 use std::{net::IpAddr, str::FromStr};
 
-#[allow(clippy::bind_instead_of_map)]
-pub fn parse(
-    line: &str,
-    ip: Option<&Regex>,
-    positive: Option<&String>,
-    negative: Option<&String>,
-) -> Result<ParsingStatus> {
-    if let Some(ne) = negative {
-        if line.contains(ne) {
-            return Ok(ParsingStatus::OkEntry);
+// regexes matched against the line as logged, or without its leading timestamp
+struct Matchers {
+    ips: Vec<Regex>,
+    positive: RegexSet,
+    negative: RegexSet,
+}
+
+pub struct GenericRules {
+    user: Matchers,
+    // imported fail2ban filters, written for lines without their timestamp
+    filters: Matchers,
+    resolver: Option<Resolver>,
+}
+
+impl GenericRules {
+    /// The ip is taken from the first of `ips` matching the line, then from the failregex
+    /// of `filters`
+    pub fn new(
+        ips: Vec<Regex>,
+        positive: &[String],
+        negative: &[String],
+        filters: &[Filter],
+    ) -> Result<GenericRules> {
+        let failregex: Vec<&String> = filters.iter().flat_map(|f| &f.failregex).collect();
+        let ignoreregex: Vec<&String> = filters.iter().flat_map(|f| &f.ignoreregex).collect();
+        if ips.is_empty() && failregex.is_empty() {
+            bail!("generic parser needs an ip regex");
         }
+        if positive.is_empty() && negative.is_empty() && failregex.is_empty() {
+            bail!("generic parser requires a positive or a negative regex");
+        }
+
+        Ok(GenericRules {
+            user: Matchers {
+                ips,
+                positive: RegexSet::new(positive).context("invalid generic positive regex")?,
+                negative: RegexSet::new(negative).context("invalid generic negative regex")?,
+            },
+            filters: Matchers {
+                ips: failregex
+                    .iter()
+                    .map(|r| Regex::new(r))
+                    .collect::<Result<_, _>>()
+                    .context("invalid fail2ban failregex")?,
+                positive: RegexSet::new(&failregex).context("invalid fail2ban failregex")?,
+                negative: RegexSet::new(&ignoreregex).context("invalid fail2ban ignoreregex")?,
+            },
+            resolver: None,
+        })
     }
 
-    if let Some(po) = positive {
-        if !line.contains(po) {
-            return Ok(ParsingStatus::OkEntry);
+    pub fn with_resolver(mut self, resolver: Resolver) -> GenericRules {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    // logged ip, or hostname when a resolver is configured
+    fn ip(&self, host: &str) -> Result<IpAddr> {
+        if let Ok(ip) = IpAddr::from_str(host) {
+            return Ok(ip);
         }
+        let resolver = self
+            .resolver
+            .as_ref()
+            .ok_or_else(|| anyhow!("cant parse generic line - ip"))?;
+        resolver
+            .lookup(host, get_epoch())?
+            .ok_or_else(|| anyhow!("cant resolve generic line host {:?}", host))
     }
+}
+
+// named group, or its numbered alternatives (ip_1, ip_2...) as imported fail2ban regexes
+// can capture the same field in several branches
+fn group<'a>(re: &Regex, captures: &Captures<'a>, name: &str) -> Option<Match<'a>> {
+    re.capture_names()
+        .flatten()
+        .filter(|n| {
+            *n == name
+                || n.strip_prefix(name)
+                    .and_then(|n| n.strip_prefix('_'))
+                    .is_some_and(|n| n.parse::<u32>().is_ok())
+        })
+        .find_map(|n| captures.name(n))
+}
 
-    let ip = ip.unwrap().captures(line);
+/// A line is bad if it matches any positive regex (or if there are none), unless it
+/// matches a negative one
+pub fn parse(line: &str, rules: &GenericRules) -> Result<ParsingStatus> {
+    let matchers = [
+        (&rules.user, line),
+        (&rules.filters, timestamp::strip(line)),
+    ];
 
-    let ip = ip
-        .and_then(|c| c.get(1))
-        .and_then(|g| Some(g.as_str()))
-        .and_then(|e| IpAddr::from_str(e).ok())
-        .ok_or_else(|| anyhow!("cant parse clf line - ip"))?;
+    if matchers.iter().any(|(m, _)| !m.positive.is_empty())
+        && !matchers.iter().any(|(m, text)| m.positive.is_match(text))
+    {
+        return Ok(ParsingStatus::OkEntry);
+    }
 
-    Ok(ParsingStatus::BadEntry(ip))
+    if matchers.iter().any(|(m, text)| m.negative.is_match(text)) {
+        return Ok(ParsingStatus::OkEntry);
+    }
+
+    let (re, captures) = matchers
+        .iter()
+        .flat_map(|(m, text)| m.ips.iter().map(move |re| (re, *text)))
+        .find_map(|(re, text)| re.captures(text).map(|c| (re, c)))
+        .ok_or_else(|| anyhow!("cant parse generic line - ip"))?;
+    let host = group(re, &captures, "ip")
+        .or_else(|| captures.get(1))
+        .ok_or_else(|| anyhow!("cant parse generic line - ip"))?;
+    let ip = rules.ip(host.as_str())?;
+
+    // best effort, generic logs often start with a syslog timestamp
+    let ts = group(re, &captures, "time")
+        .and_then(|t| timestamp::any(t.as_str()))
+        .or_else(|| timestamp::syslog(line));
+    let user = group(re, &captures, "user").map(|u| u.as_str().to_string());
+
+    Ok(ParsingStatus::BadEntry(
+        Offence::new(ip, ts).with_user(user),
+    ))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::dns::Resolution;
+    use crate::utils::TempDir;
 
     const FAILED: &str =
         "Sep 26 06:25:19 livecompute sshd[23246]: Failed password for root from 179.124.36.195 port 41883 ssh2";
@@ -43,19 +143,24 @@ mod tests {
     const SUCCESS: &str =
         "Sep 26 06:25:19 livecompute sshd[23246]: Successful login for root from 179.124.36.195 port 41883 ssh2";
 
+    fn rules(positive: &[&str], negative: &[&str]) -> GenericRules {
+        let ip = Regex::new(r"from ([0-9a-fA-F:.]+) port").unwrap();
+        let strings = |r: &[&str]| r.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+        GenericRules::new(vec![ip], &strings(positive), &strings(negative), &[]).unwrap()
+    }
+
     // generic log positive regex - what's that's flagged by this is considered bad, the rest is good
     #[test]
     fn positive() {
-        let positive = "Failed password".to_string();
-        let ip = Regex::new(r"from ([0-9a-fA-F:.]+) port").unwrap();
+        let rules = rules(&["Failed password"], &[]);
 
-        let ret = parse(FAILED, Some(&ip), Some(&positive), None).unwrap();
+        let ret = parse(FAILED, &rules).unwrap();
         match ret {
             ParsingStatus::BadEntry(_) => {}
             _ => panic!("bad parsing"),
         }
 
-        let ret = parse(SUCCESS, Some(&ip), Some(&positive), None).unwrap();
+        let ret = parse(SUCCESS, &rules).unwrap();
         match ret {
             ParsingStatus::OkEntry => {}
             _ => panic!("bad parsing"),
@@ -65,19 +170,135 @@ mod tests {
     // generic log negative regex - what's that's flagged by this is considered good, the rest is bad
     #[test]
     fn negative() {
-        let negative = "Successful login".to_string();
-        let ip = Regex::new(r"from ([0-9a-fA-F:.]+) port").unwrap();
+        let rules = rules(&[], &["Successful login"]);
 
-        let ret = parse(SUCCESS, Some(&ip), None, Some(&negative)).unwrap();
+        let ret = parse(SUCCESS, &rules).unwrap();
         match ret {
             ParsingStatus::OkEntry => {}
             _ => panic!("bad parsing"),
         }
 
-        let ret = parse(FAILED, Some(&ip), None, Some(&negative)).unwrap();
+        let ret = parse(FAILED, &rules).unwrap();
         match ret {
             ParsingStatus::BadEntry(_) => {}
             _ => panic!("bad parsing"),
         }
     }
+
+    // bad if it matches any positive, unless it also matches a negative
+    #[test]
+    fn combined() {
+        let rules = rules(
+            &[r"Failed (password|publickey)", r"Invalid user \w+"],
+            &[r"for (backup|deploy) from"],
+        );
+
+        let invalid = "Sep 26 06:25:19 livecompute sshd[23246]: Invalid user neal from 35.184.211.144 port 22";
+        assert!(matches!(
+            parse(invalid, &rules).unwrap(),
+            ParsingStatus::BadEntry(_)
+        ));
+        assert!(matches!(
+            parse(FAILED, &rules).unwrap(),
+            ParsingStatus::BadEntry(_)
+        ));
+        assert!(matches!(
+            parse(SUCCESS, &rules).unwrap(),
+            ParsingStatus::OkEntry
+        ));
+
+        let allowed = "Sep 26 06:25:19 livecompute sshd[23246]: Failed publickey for deploy from 179.124.36.195 port 41883 ssh2";
+        assert!(matches!(
+            parse(allowed, &rules).unwrap(),
+            ParsingStatus::OkEntry
+        ));
+
+        let ip = Regex::new(r"from (\S+)").unwrap();
+        assert!(GenericRules::new(vec![ip.clone()], &[], &[], &[]).is_err());
+        assert!(GenericRules::new(vec![ip], &["(".to_string()], &[], &[]).is_err());
+        assert!(GenericRules::new(vec![], &["Failed".to_string()], &[], &[]).is_err());
+    }
+
+    #[test]
+    fn named_groups() {
+        let ip = Regex::new(r"^(?P<time>\S+) .*user=(?P<user>\S+) rhost=(?P<ip>\S+)").unwrap();
+        let rules =
+            GenericRules::new(vec![ip], &["authentication failure".to_string()], &[], &[]).unwrap();
+
+        let line = "2021-09-26T06:25:19Z host pam: authentication failure; user=admin rhost=179.124.36.195";
+        match parse(line, &rules).unwrap() {
+            ParsingStatus::BadEntry(o) => {
+                assert_eq!(o.ip, "179.124.36.195".parse::<IpAddr>().unwrap());
+                assert_eq!(o.ts, Some(1632637519));
+                assert_eq!(o.user.as_deref(), Some("admin"));
+            }
+            _ => panic!("bad parsing"),
+        }
+
+        // hostnames need a resolver
+        let line = "2021-09-26T06:25:19Z host pam: authentication failure; user=admin rhost=scanner.example.net";
+        assert!(parse(line, &rules).is_err());
+
+        let dir = TempDir::new("generic");
+        let hosts = dir.0.join("hosts");
+        std::fs::write(&hosts, "192.0.2.7 scanner.example.net\n").unwrap();
+        let rules =
+            rules.with_resolver(Resolver::new(Some(&hosts), None, Resolution::Blocking).unwrap());
+
+        match parse(line, &rules).unwrap() {
+            ParsingStatus::BadEntry(o) => {
+                assert_eq!(o.ip, "192.0.2.7".parse::<IpAddr>().unwrap())
+            }
+            _ => panic!("bad parsing"),
+        }
+        let line = "2021-09-26T06:25:19Z host pam: authentication failure; user=admin rhost=other.example.net";
+        assert!(parse(line, &rules).is_err());
+    }
+
+    // filters match lines without their timestamp, the user's regexes the line as logged
+    #[test]
+    fn fail2ban_filters() {
+        let filter = Filter {
+            failregex: vec![
+                r"^\S+ sshd\[\d+\]: Failed password for (?P<user>\S+) from (?P<ip>\S+)".to_string(),
+            ],
+            ignoreregex: vec![r"for backup from".to_string()],
+        };
+        let ip = Regex::new(r"rhost=(\S+)").unwrap();
+        let positive = [r"^Sep \d+ \S+ \S+ pam: authentication failure".to_string()];
+        let rules = GenericRules::new(vec![ip], &positive, &[], &[filter]).unwrap();
+
+        match parse(FAILED, &rules).unwrap() {
+            ParsingStatus::BadEntry(o) => {
+                assert_eq!(o.ip, "179.124.36.195".parse::<IpAddr>().unwrap());
+                assert_eq!(o.user.as_deref(), Some("root"));
+            }
+            _ => panic!("bad parsing"),
+        }
+
+        let line = "Sep 26 06:25:19 livecompute pam: authentication failure; rhost=35.184.211.144";
+        match parse(line, &rules).unwrap() {
+            ParsingStatus::BadEntry(o) => {
+                assert_eq!(o.ip, "35.184.211.144".parse::<IpAddr>().unwrap())
+            }
+            _ => panic!("bad parsing"),
+        }
+
+        let ignored = "Sep 26 06:25:19 livecompute sshd[23246]: Failed password for backup from 179.124.36.195 port 41883 ssh2";
+        assert!(matches!(
+            parse(ignored, &rules).unwrap(),
+            ParsingStatus::OkEntry
+        ));
+        assert!(matches!(
+            parse(SUCCESS, &rules).unwrap(),
+            ParsingStatus::OkEntry
+        ));
+
+        // filters alone are enough
+        let filter = Filter {
+            failregex: vec![r"from (?P<ip>\S+)".to_string()],
+            ignoreregex: vec![],
+        };
+        assert!(GenericRules::new(vec![], &[], &[], &[filter]).is_ok());
+    }
 }
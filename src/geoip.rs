@@ -0,0 +1,287 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::result::Result::Ok;
+use std::str::FromStr;
+
+use anyhow::*;
+use maxminddb::{geoip2, Reader};
+
+use crate::utils::{log, Args};
+
+/// Where an ip is from, as far as the databases know
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Geo {
+    pub country: Option<String>,
+    pub asn: Option<u32>,
+    pub org: Option<String>,
+}
+
+impl fmt::Display for Geo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = vec![];
+        if let Some(country) = &self.country {
+            parts.push(country.clone());
+        }
+        if let Some(asn) = self.asn {
+            parts.push(format!("AS{}", asn));
+        }
+        if let Some(org) = &self.org {
+            parts.push(org.clone());
+        }
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+/// A country code, e.g. DE, or an ASN, e.g. AS3320
+#[derive(Clone, Debug, PartialEq)]
+pub enum Area {
+    Country(String),
+    Asn(u32),
+}
+
+pub fn parse_area(a: &str) -> Result<Area> {
+    let upper = a.to_ascii_uppercase();
+    if let Some(asn) = upper.strip_prefix("AS") {
+        let asn = u32::from_str(asn).map_err(|_| anyhow!("invalid ASN {:?}", a))?;
+        return Ok(Area::Asn(asn));
+    }
+    ensure!(
+        upper.len() == 2 && upper.chars().all(|c| c.is_ascii_uppercase()),
+        "invalid country {:?}, expected a 2 letters code or an ASN like AS3320",
+        a
+    );
+    Ok(Area::Country(upper))
+}
+
+impl Area {
+    fn contains(&self, geo: &Geo) -> bool {
+        match self {
+            Area::Country(c) => geo.country.as_ref() == Some(c),
+            Area::Asn(a) => geo.asn == Some(*a),
+        }
+    }
+}
+
+/// Local MaxMind or DB-IP databases, country and ASN ones, and the allowances they drive
+pub struct GeoIp {
+    readers: Vec<Reader<Vec<u8>>>,
+    strict: Vec<Area>,
+    strict_allowance: u8,
+    lenient: Vec<Area>,
+    lenient_allowance: u8,
+}
+
+impl GeoIp {
+    pub fn new(paths: &[PathBuf]) -> Result<GeoIp> {
+        let readers = paths
+            .iter()
+            .map(|p| {
+                Reader::open_readfile(p)
+                    .with_context(|| format!("cant open geoip database {:?}", p))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(GeoIp {
+            readers,
+            strict: vec![],
+            strict_allowance: 0,
+            lenient: vec![],
+            lenient_allowance: 0,
+        })
+    }
+
+    /// From the geoip args, none without databases
+    pub fn from_args(args: &Args) -> Result<Option<GeoIp>> {
+        if args.geoip_db.is_empty() {
+            ensure!(
+                args.geoip_strict.is_empty() && args.geoip_lenient.is_empty(),
+                "geoip policies need a database, see --geoip-db"
+            );
+            return Ok(None);
+        }
+        let geoip = GeoIp::new(&args.geoip_db)?
+            .with_strict(&args.geoip_strict, args.geoip_strict_allowance)
+            .with_lenient(&args.geoip_lenient, args.geoip_lenient_allowance);
+        log!("geoip enabled with {} databases", args.geoip_db.len());
+        Ok(Some(geoip))
+    }
+
+    pub fn with_strict(mut self, areas: &[Area], allowance: u8) -> GeoIp {
+        self.strict = areas.to_vec();
+        self.strict_allowance = allowance;
+        self
+    }
+
+    pub fn with_lenient(mut self, areas: &[Area], allowance: u8) -> GeoIp {
+        self.lenient = areas.to_vec();
+        self.lenient_allowance = allowance;
+        self
+    }
+
+    // databases have either kind of records, or both. Unknown ips are just not annotated
+    pub fn lookup(&self, ip: IpAddr) -> Geo {
+        let mut geo = Geo::default();
+        for reader in &self.readers {
+            if let Ok(c) = reader.lookup::<geoip2::Country>(ip) {
+                let country = c.country.or(c.registered_country);
+                if let Some(code) = country.and_then(|c| c.iso_code) {
+                    geo.country.get_or_insert_with(|| code.to_string());
+                }
+            }
+            if let Ok(a) = reader.lookup::<geoip2::Asn>(ip) {
+                if let Some(asn) = a.autonomous_system_number {
+                    geo.asn.get_or_insert(asn);
+                }
+                if let Some(org) = a.autonomous_system_organization {
+                    geo.org.get_or_insert_with(|| org.to_string());
+                }
+            }
+        }
+        geo
+    }
+
+    /// Lenient areas win over strict ones, so that users are not locked out
+    pub fn allowance(&self, geo: &Geo, default: u8) -> u8 {
+        if self.is_lenient(geo) {
+            return self.lenient_allowance;
+        }
+        if self.strict.iter().any(|a| a.contains(geo)) {
+            return self.strict_allowance;
+        }
+        default
+    }
+
+    /// Lenient areas are never banned instantly, their instant offences count against the
+    /// lenient allowance like the others
+    pub fn allows_instant(&self, geo: &Geo) -> bool {
+        !self.is_lenient(geo)
+    }
+
+    fn is_lenient(&self, geo: &Geo) -> bool {
+        self.lenient.iter().any(|a| a.contains(geo))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+
+    // MMDB encoding of the few types needed, see https://maxmind.github.io/MaxMind-DB/
+    fn string(s: &str) -> Vec<u8> {
+        let mut v = match s.len() {
+            n if n < 29 => vec![0x40 | n as u8],
+            n => vec![0x40 | 29, (n - 29) as u8],
+        };
+        v.extend(s.as_bytes());
+        v
+    }
+
+    fn uint(ctrl: u8, n: u64, len: usize) -> Vec<u8> {
+        let mut v = vec![ctrl | len as u8];
+        v.extend(&n.to_be_bytes()[8 - len..]);
+        v
+    }
+
+    fn map(pairs: Vec<(&str, Vec<u8>)>) -> Vec<u8> {
+        let mut v = vec![0xe0 | pairs.len() as u8];
+        for (k, val) in pairs {
+            v.extend(string(k));
+            v.extend(val);
+        }
+        v
+    }
+
+    fn record(country: &str, asn: u32, org: &str) -> Vec<u8> {
+        map(vec![
+            ("country", map(vec![("iso_code", string(country))])),
+            ("autonomous_system_number", uint(0xc0, asn as u64, 4)),
+            ("autonomous_system_organization", string(org)),
+        ])
+    }
+
+    // a single node IPv4 tree: 0.0.0.0/1 in one record, 128.0.0.0/1 in the other
+    fn database() -> Vec<u8> {
+        let low = record("DE", 3320, "Deutsche Telekom AG");
+        let high = record("CN", 4134, "Chinanet");
+
+        let node_count = 1;
+        let mut db = vec![];
+        for offset in [0, low.len()] {
+            db.extend(&((node_count + 16 + offset) as u32).to_be_bytes()[1..]);
+        }
+        db.extend([0; 16]);
+        db.extend(low);
+        db.extend(high);
+
+        db.extend(b"\xab\xcd\xefMaxMind.com");
+        db.extend(map(vec![
+            ("binary_format_major_version", uint(0xa0, 2, 2)),
+            ("binary_format_minor_version", uint(0xa0, 0, 2)),
+            (
+                "build_epoch",
+                vec![0x08, 0x02, 0, 0, 0, 0, 0x61, 0x50, 0x10, 0x8f],
+            ),
+            ("database_type", string("blockfast-test")),
+            ("description", map(vec![])),
+            ("ip_version", uint(0xa0, 4, 2)),
+            ("languages", vec![0x00, 0x04]),
+            ("node_count", uint(0xc0, node_count as u64, 4)),
+            ("record_size", uint(0xa0, 24, 2)),
+        ]));
+        db
+    }
+
+    fn geoip(name: &str) -> GeoIp {
+        let dir = TempDir::new(&format!("geoip-{}", name));
+        let path = dir.0.join("test.mmdb");
+        std::fs::write(&path, database()).unwrap();
+        GeoIp::new(&[path]).unwrap()
+    }
+
+    #[test]
+    fn areas() {
+        assert_eq!(parse_area("de").unwrap(), Area::Country("DE".to_string()));
+        assert_eq!(parse_area("AS3320").unwrap(), Area::Asn(3320));
+        assert_eq!(parse_area("as4134").unwrap(), Area::Asn(4134));
+        assert!(parse_area("DEU").is_err());
+        assert!(parse_area("ASN").is_err());
+        assert!(parse_area("4134").is_err());
+    }
+
+    #[test]
+    fn lookups() {
+        let geoip = geoip("lookups");
+
+        let geo = geoip.lookup("35.184.211.144".parse().unwrap());
+        assert_eq!(geo.country.as_deref(), Some("DE"));
+        assert_eq!(geo.asn, Some(3320));
+        assert_eq!(geo.to_string(), "DE AS3320 Deutsche Telekom AG");
+
+        let geo = geoip.lookup("179.124.36.195".parse().unwrap());
+        assert_eq!(geo.to_string(), "CN AS4134 Chinanet");
+
+        assert!(GeoIp::new(&[PathBuf::from("/nonexistent.mmdb")]).is_err());
+    }
+
+    #[test]
+    fn allowances() {
+        let geoip = geoip("allowances")
+            .with_strict(
+                &[parse_area("CN").unwrap(), parse_area("AS3320").unwrap()],
+                1,
+            )
+            .with_lenient(&[parse_area("AS3320").unwrap()], 20);
+
+        let home = geoip.lookup("35.184.211.144".parse().unwrap());
+        assert_eq!(geoip.allowance(&home, 5), 20);
+        let away = geoip.lookup("179.124.36.195".parse().unwrap());
+        assert_eq!(geoip.allowance(&away, 5), 1);
+        assert_eq!(geoip.allowance(&Geo::default(), 5), 5);
+
+        assert!(!geoip.allows_instant(&home));
+        assert!(geoip.allows_instant(&away));
+        assert!(geoip.allows_instant(&Geo::default()));
+    }
+}
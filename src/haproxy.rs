@@ -0,0 +1,112 @@
+use crate::http::{self, HttpRequest, HttpRules};
+use crate::timestamp;
+use crate::utils::ParsingStatus;
+use anyhow::*;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{net::IpAddr, str::FromStr};
+
+lazy_static! {
+    // client_ip:port [accept_date] frontend backend/server timers status_code bytes
+    static ref RE_HTTP_LOG: Regex = Regex::new(
+        r"(?:^|\s)([0-9a-fA-F:.]+):\d+ \[([^\]]+)\] \S+ \S+ \S+ (-?\d+) "
+    )
+    .unwrap();
+    static ref RE_REQUEST: Regex = Regex::new(r#""\S+ (\S+)[^"]*"$"#).unwrap();
+}
+
+// haproxy's HTTP log format (option httplog)
+pub fn request(line: &str) -> Result<HttpRequest> {
+    let c = RE_HTTP_LOG
+        .captures(line)
+        .ok_or_else(|| anyhow!("cant parse haproxy line"))?;
+
+    let ip = IpAddr::from_str(&c[1]).map_err(|_| anyhow!("cant parse haproxy line - ip"))?;
+
+    // -1 when the connection was aborted before a response
+    let status = c[3]
+        .parse::<u32>()
+        .map_err(|_| anyhow!("cant parse haproxy line - status"))?;
+
+    let path = RE_REQUEST
+        .captures(line)
+        .and_then(|c| c.get(1))
+        .map(|g| g.as_str().to_string());
+
+    let ts = timestamp::haproxy(&c[2]).or_else(|| timestamp::syslog(line));
+
+    Ok(HttpRequest {
+        ip,
+        path,
+        user_agent: None,
+        status,
+        ts,
+    })
+}
+
+/// `source_ts` is the time given by the source, if the line has none
+pub fn parse(
+    line: &str,
+    valid_statuses: &[u32],
+    rules: &HttpRules,
+    source_ts: Option<u64>,
+) -> Result<ParsingStatus> {
+    let mut req = request(line)?;
+    req.ts = req.ts.or(source_ts);
+    http::assess(&req, valid_statuses, rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positive() {
+        let vectors = [
+            r#"Sep 26 06:25:19 lb haproxy[4242]: 179.124.36.195:41883 [26/Sep/2021:06:25:19.123] fe_https~ be_app/web1 0/0/1/2/3 401 923 - - ---- 1/1/0/0/0 0/0 "POST /login HTTP/1.1""#,
+            r#"Sep 26 06:25:19 lb haproxy[4242]: 2001:db8::1:41883 [26/Sep/2021:06:25:19.123] fe_http be_app/web1 0/0/1/2/3 403 923 - - ---- 1/1/0/0/0 0/0 {app.example.net} "GET /admin HTTP/1.1""#,
+        ];
+
+        vectors.iter().for_each(|e| {
+            let ret = parse(e, &[200, 404], &HttpRules::default(), None).unwrap();
+            match ret {
+                ParsingStatus::BadEntry(_) => {}
+                _ => panic!("bad parsing"),
+            }
+        });
+
+        let req = request(vectors[1]).unwrap();
+        assert_eq!(req.ip, "2001:db8::1".parse::<IpAddr>().unwrap());
+        assert_eq!(req.path.as_deref(), Some("/admin"));
+        assert_eq!(req.status, 403);
+        assert!(req.ts.is_some());
+    }
+
+    #[test]
+    fn negative() {
+        let vectors = [
+            r#"Sep 26 06:25:19 lb haproxy[4242]: 179.124.36.195:41883 [26/Sep/2021:06:25:19.123] fe_https~ be_app/web1 0/0/1/2/3 200 923 - - ---- 1/1/0/0/0 0/0 "GET / HTTP/1.1""#,
+            r#"Sep 26 06:25:19 lb haproxy[4242]: 179.124.36.195:41883 [26/Sep/2021:06:25:19.123] fe_https~ be_app/web1 0/0/1/2/3 404 923 - - ---- 1/1/0/0/0 0/0 "GET /favicon.ico HTTP/1.1""#,
+        ];
+
+        vectors.iter().for_each(|e| {
+            let ret = parse(e, &[200, 404], &HttpRules::default(), None).unwrap();
+            match ret {
+                ParsingStatus::OkEntry => {}
+                _ => panic!("bad parsing"),
+            }
+        })
+    }
+
+    #[test]
+    fn malformed() {
+        let vectors = [
+            "Sep 26 06:25:19 lb haproxy[4242]: Proxy fe_http started.",
+            r#"Sep 26 06:25:19 lb haproxy[4242]: 179.124.36.195:41883 [26/Sep/2021:06:25:19.123] fe_http be_app/<NOSRV> -1/-1/-1/-1/0 -1 0 - - CR-- 1/1/0/0/0 0/0 "<BADREQ>""#,
+        ];
+
+        vectors.iter().for_each(|e| {
+            parse(e, &[200, 404], &HttpRules::default(), None).expect_err("");
+        })
+    }
+}
@@ -0,0 +1,172 @@
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::result::Result::Ok;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::*;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::Semaphore;
+
+use crate::utils::{get_epoch, log, Offence, ParsingStatus};
+
+// a byte every so often keeps most clients waiting, without costing much
+const TARPIT_DELAY: Duration = Duration::from_secs(10);
+
+// connections held at once, past this they're closed right away - well below the usual
+// 1024 open files limit, which the tailed logs, sockets and listeners share
+const MAX_TARPITTED: usize = 256;
+
+// out of file descriptors and the likes, tarpitted connections have to go away first
+const ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// A port to listen on, on all interfaces, or an address
+pub fn parse_honeypot(a: &str) -> Result<SocketAddr> {
+    if let Ok(port) = u16::from_str(a) {
+        return Ok(SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port));
+    }
+    SocketAddr::from_str(a)
+        .map_err(|_| anyhow!("invalid honeypot {:?}, expected port or ip:port", a))
+}
+
+// sent as is, never as a line to parse that other sources could forge
+fn offence(peer: SocketAddr, instant_ban: bool) -> ParsingStatus {
+    let offence = Offence::new(peer.ip().to_canonical(), Some(get_epoch()));
+    if instant_ban {
+        return ParsingStatus::InstantBan(offence);
+    }
+    ParsingStatus::BadEntry(offence)
+}
+
+async fn tarpit(mut stream: TcpStream) {
+    let mut i: u8 = 0;
+    loop {
+        tokio::time::sleep(TARPIT_DELAY).await;
+        i = i.wrapping_add(1);
+        let byte = if i % 32 == 0 { b'\n' } else { b'a' + i % 26 };
+        if stream.write_all(&[byte]).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Any connection is an offence, held open and trickled to when tarpitting
+pub async fn listen(
+    addr: SocketAddr,
+    tarpit_enabled: bool,
+    instant_ban: bool,
+    tx: Sender<ParsingStatus>,
+) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("cant listen on honeypot {}", addr))?;
+    let tarpitted = Arc::new(Semaphore::new(MAX_TARPITTED));
+
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                log!("ERR: honeypot {} cant accept connection {:?}", addr, e);
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+        tx.send(offence(peer, instant_ban)).await?;
+
+        if !tarpit_enabled {
+            continue;
+        }
+        match tarpitted.clone().try_acquire_owned() {
+            Ok(permit) => {
+                tokio::spawn(async move {
+                    tarpit(stream).await;
+                    drop(permit);
+                });
+            }
+            Err(_) => {
+                log!("honeypot tarpit full, closing connection from {}", peer);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::Kind;
+    use tokio::io::AsyncReadExt;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn honeypots() {
+        assert_eq!(
+            parse_honeypot("2323").unwrap(),
+            SocketAddr::from_str("[::]:2323").unwrap()
+        );
+        assert_eq!(
+            parse_honeypot("127.0.0.1:23").unwrap(),
+            SocketAddr::from_str("127.0.0.1:23").unwrap()
+        );
+        assert!(parse_honeypot("telnet").is_err());
+        assert!(parse_honeypot("70000").is_err());
+
+        // not a parser log sources can route lines to
+        assert!(Kind::from_str("honeypot").is_err());
+    }
+
+    #[test]
+    fn offences() {
+        let peer = SocketAddr::from_str("179.124.36.195:41883").unwrap();
+        match offence(peer, false) {
+            ParsingStatus::BadEntry(o) => {
+                assert_eq!(o.ip, "179.124.36.195".parse::<IpAddr>().unwrap())
+            }
+            _ => panic!("bad offence"),
+        }
+        let peer = SocketAddr::from_str("[2001:db8::1]:41883").unwrap();
+        match offence(peer, true) {
+            ParsingStatus::InstantBan(o) => {
+                assert_eq!(o.ip, "2001:db8::1".parse::<IpAddr>().unwrap())
+            }
+            _ => panic!("bad offence"),
+        }
+
+        // mapped ipv4 peers of dual stack listeners
+        let peer = SocketAddr::from_str("[::ffff:179.124.36.195]:41883").unwrap();
+        match offence(peer, false) {
+            ParsingStatus::BadEntry(o) => {
+                assert_eq!(o.ip, "179.124.36.195".parse::<IpAddr>().unwrap())
+            }
+            _ => panic!("bad offence"),
+        }
+    }
+
+    #[tokio::test]
+    async fn listener() {
+        // grab a free port
+        let port = {
+            let l = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            l.local_addr().unwrap().port()
+        };
+        let addr = SocketAddr::from_str(&format!("127.0.0.1:{}", port)).unwrap();
+
+        let (tx, mut rx) = mpsc::channel(8);
+        tokio::spawn(listen(addr, true, true, tx));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        match rx.recv().await.unwrap() {
+            ParsingStatus::InstantBan(o) => {
+                assert_eq!(o.ip, "127.0.0.1".parse::<IpAddr>().unwrap())
+            }
+            _ => panic!("bad offence"),
+        }
+
+        // tarpitted, still open with nothing sent yet
+        let mut buf = [0; 1];
+        let read = tokio::time::timeout(Duration::from_millis(200), client.read(&mut buf)).await;
+        assert!(read.is_err());
+    }
+}
@@ -0,0 +1,240 @@
+use anyhow::*;
+use regex::RegexSet;
+use std::net::IpAddr;
+
+use crate::rate::RateLimiter;
+use crate::utils::{Offence, ParsingStatus, TimeSource};
+
+// paths almost exclusively requested by scanners
+const SCANNER_PATHS: [&str; 8] = [
+    r"(?i)/wp-login\.php",
+    r"(?i)/xmlrpc\.php",
+    r"/\.env",
+    r"/\.git/",
+    r"(?i)/phpmyadmin",
+    r"(?i)/cgi-bin/",
+    r"(?i)/vendor/phpunit/",
+    r"(?i)/boaform/",
+];
+
+// user-agents of well known scanning tools
+const SCANNER_USER_AGENTS: [&str; 6] = [
+    r"(?i)zgrab",
+    r"(?i)masscan",
+    r"(?i)nmap",
+    r"(?i)sqlmap",
+    r"(?i)nikto",
+    r"(?i)nuclei",
+];
+
+#[derive(Debug)]
+pub struct HttpRequest {
+    pub ip: IpAddr,
+    pub path: Option<String>,
+    pub user_agent: Option<String>,
+    pub status: u32,
+    pub ts: Option<u64>,
+}
+
+pub struct HttpRules {
+    paths: RegexSet,
+    user_agents: RegexSet,
+    instant_ban: bool,
+    rate: Option<(RateLimiter, TimeSource)>,
+}
+
+impl HttpRules {
+    pub fn new(
+        scanner_rules: bool,
+        bad_paths: &[String],
+        bad_user_agents: &[String],
+        instant_ban: bool,
+    ) -> Result<HttpRules> {
+        let mut paths: Vec<&str> = bad_paths.iter().map(|p| p.as_str()).collect();
+        let mut user_agents: Vec<&str> = bad_user_agents.iter().map(|u| u.as_str()).collect();
+        if scanner_rules {
+            paths.extend(SCANNER_PATHS);
+            user_agents.extend(SCANNER_USER_AGENTS);
+        }
+
+        Ok(HttpRules {
+            paths: RegexSet::new(paths).context("invalid http path regex")?,
+            user_agents: RegexSet::new(user_agents).context("invalid http user-agent regex")?,
+            instant_ban,
+            rate: None,
+        })
+    }
+
+    pub fn with_rate_limit(mut self, rate: RateLimiter, time_source: TimeSource) -> HttpRules {
+        self.rate = Some((rate, time_source));
+        self
+    }
+
+    fn is_flooding(&self, req: &HttpRequest) -> Result<bool> {
+        match &self.rate {
+            Some((rate, time_source)) if !rate.is_exempt(req.path.as_deref()) => {
+                rate.hit(req.ip, time_source.pick(req.ts))
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn is_flagged(&self, req: &HttpRequest) -> bool {
+        let path = req.path.as_ref().is_some_and(|p| self.paths.is_match(p));
+        let ua = req
+            .user_agent
+            .as_ref()
+            .is_some_and(|u| self.user_agents.is_match(u));
+        path || ua
+    }
+}
+
+impl Default for HttpRules {
+    fn default() -> Self {
+        HttpRules {
+            paths: RegexSet::empty(),
+            user_agents: RegexSet::empty(),
+            instant_ban: false,
+            rate: None,
+        }
+    }
+}
+
+/// Scanner rules and rate limit only, the status is left to the caller
+pub fn assess_rules(req: &HttpRequest, rules: &HttpRules) -> Result<Option<ParsingStatus>> {
+    let offence = || Offence::new(req.ip, req.ts);
+
+    // flagged paths and user-agents are bad regardless of the status
+    if rules.is_flagged(req) {
+        if rules.instant_ban {
+            return Ok(Some(ParsingStatus::InstantBan(offence())));
+        }
+        return Ok(Some(ParsingStatus::BadEntry(offence())));
+    }
+
+    // so are floods, they're banned right away
+    if rules.is_flooding(req)? {
+        return Ok(Some(ParsingStatus::InstantBan(offence())));
+    }
+
+    Ok(None)
+}
+
+pub fn assess(
+    req: &HttpRequest,
+    valid_statuses: &[u32],
+    rules: &HttpRules,
+) -> Result<ParsingStatus> {
+    if let Some(status) = assess_rules(req, rules)? {
+        return Ok(status);
+    }
+
+    let is_good_status = valid_statuses.iter().any(|s| s == &req.status);
+    if !is_good_status {
+        return Ok(ParsingStatus::BadEntry(Offence::new(req.ip, req.ts)));
+    }
+
+    Ok(ParsingStatus::OkEntry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn req(path: &str, user_agent: &str, status: u32) -> HttpRequest {
+        HttpRequest {
+            ip: IpAddr::from_str("8.8.8.8").unwrap(),
+            path: Some(path.to_string()),
+            user_agent: Some(user_agent.to_string()),
+            status,
+            ts: None,
+        }
+    }
+
+    #[test]
+    fn scanner_rules() {
+        let rules = HttpRules::new(true, &[], &[], false).unwrap();
+
+        let vectors = [
+            req("/wp-login.php", "Mozilla/5.0", 200),
+            req("/.env", "Mozilla/5.0", 404),
+            req("/app/.git/config", "Mozilla/5.0", 404),
+            req("/phpMyAdmin/index.php", "Mozilla/5.0", 404),
+            req("/", "Mozilla/5.0 zgrab/0.x", 200),
+            req(
+                "/",
+                "masscan/1.3 (https://github.com/robertdavidgraham/masscan)",
+                200,
+            ),
+        ];
+
+        vectors
+            .iter()
+            .for_each(|e| match assess(e, &[200, 404], &rules).unwrap() {
+                ParsingStatus::BadEntry(_) => {}
+                _ => panic!("bad parsing"),
+            });
+
+        match assess(&req("/index.html", "Mozilla/5.0", 200), &[200, 404], &rules).unwrap() {
+            ParsingStatus::OkEntry => {}
+            _ => panic!("bad parsing"),
+        }
+    }
+
+    #[test]
+    fn user_rules() {
+        let paths = ["^/admin".to_string()];
+        let uas = ["(?i)badbot".to_string()];
+        let rules = HttpRules::new(false, &paths, &uas, true).unwrap();
+
+        match assess(&req("/admin/login", "Mozilla/5.0", 200), &[200], &rules).unwrap() {
+            ParsingStatus::InstantBan(_) => {}
+            _ => panic!("bad parsing"),
+        }
+
+        match assess(&req("/", "BadBot/2.1", 200), &[200], &rules).unwrap() {
+            ParsingStatus::InstantBan(_) => {}
+            _ => panic!("bad parsing"),
+        }
+
+        // default bundle is opt-in
+        match assess(&req("/wp-login.php", "Mozilla/5.0", 200), &[200], &rules).unwrap() {
+            ParsingStatus::OkEntry => {}
+            _ => panic!("bad parsing"),
+        }
+    }
+
+    #[test]
+    fn flood() {
+        let rate = RateLimiter::new(3, 60, &[]).unwrap();
+        let rules = HttpRules::new(false, &[], &[], false)
+            .unwrap()
+            .with_rate_limit(rate, TimeSource::Ingest);
+
+        // static assets aren't counted
+        for _ in 0..10 {
+            match assess(&req("/app.css", "Mozilla/5.0", 200), &[200], &rules).unwrap() {
+                ParsingStatus::OkEntry => {}
+                _ => panic!("bad parsing"),
+            }
+        }
+
+        for _ in 0..3 {
+            match assess(&req("/", "Mozilla/5.0", 200), &[200], &rules).unwrap() {
+                ParsingStatus::OkEntry => {}
+                _ => panic!("bad parsing"),
+            }
+        }
+
+        match assess(&req("/", "Mozilla/5.0", 200), &[200], &rules).unwrap() {
+            ParsingStatus::InstantBan(_) => {}
+            _ => panic!("bad parsing"),
+        }
+    }
+
+    #[test]
+    fn invalid_rules() {
+        assert!(HttpRules::new(false, &["(".to_string()], &[], false).is_err());
+    }
+}
@@ -0,0 +1,111 @@
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::sync::mpsc::Sender;
+
+use crate::follow::{Follower, POLL_INTERVAL};
+use crate::parsers::Kind;
+
+/// A line to parse, from any source
+#[derive(Debug)]
+pub struct Entry {
+    pub kind: Kind,
+    pub line: String,
+    /// event time given by the source, if the line itself has none
+    pub ts: Option<u64>,
+    /// journal position, saved once the line is assessed
+    pub cursor: Option<String>,
+}
+
+pub const STDIN: &str = "-";
+
+/// stdin and named pipes can't be tailed like files
+pub fn is_stream(path: &Path) -> bool {
+    path == Path::new(STDIN)
+        || std::fs::metadata(path)
+            .map(|m| m.file_type().is_fifo())
+            .unwrap_or(false)
+}
+
+async fn read_lines<R: AsyncRead + Unpin>(kind: Kind, r: R, tx: &Sender<Entry>) -> Result<()> {
+    let mut split = BufReader::new(r).split(b'\n');
+    while let Some(line) = split.next_segment().await? {
+        let entry = Entry {
+            kind,
+            line: String::from_utf8_lossy(&line).to_string(),
+            ts: None,
+            cursor: None,
+        };
+        tx.send(entry).await?;
+    }
+    Ok(())
+}
+
+pub async fn read_stream(kind: Kind, path: PathBuf, tx: Sender<Entry>) -> Result<()> {
+    if path == Path::new(STDIN) {
+        return read_lines(kind, tokio::io::stdin(), &tx).await;
+    }
+
+    // a fifo reaches eof when its writer goes away, reopening waits for the next one
+    loop {
+        let fifo = tokio::fs::File::open(&path)
+            .await
+            .with_context(|| format!("cant open {:?}", path))?;
+        read_lines(kind, fifo, &tx).await?;
+    }
+}
+
+/// Follows a logfile, see [Follower]
+pub async fn tail(kind: Kind, mut follower: Follower, tx: Sender<Entry>) -> Result<()> {
+    loop {
+        for line in follower.poll().await {
+            tx.send(Entry {
+                kind,
+                line,
+                ts: None,
+                cursor: None,
+            })
+            .await?;
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn stream() {
+        let (tx, mut rx) = mpsc::channel(8);
+        let input: &[u8] = b"first\nsecond \xff\nthird";
+        read_lines(Kind::Json, input, &tx).await.unwrap();
+        drop(tx);
+
+        let mut lines = vec![];
+        while let Some(entry) = rx.recv().await {
+            assert_eq!(entry.kind, Kind::Json);
+            lines.push(entry.line);
+        }
+        assert_eq!(lines, ["first", "second \u{fffd}", "third"]);
+    }
+
+    #[test]
+    fn streams() {
+        let dir = TempDir::new("fifo");
+        let fifo = dir.0.join("fifo");
+        let status = std::process::Command::new("mkfifo")
+            .arg(&fifo)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        assert!(is_stream(Path::new("-")));
+        assert!(is_stream(&fifo));
+        assert!(!is_stream(&dir.0));
+    }
+}
@@ -7,14 +7,19 @@ use anyhow::*;
 
 use crate::utils::{get_epoch, log};
 
+enum Backend {
+    Ipset(String),
+    Memory,
+}
+
 pub struct Jail {
-    name: String,
+    backend: Backend,
     allowance: u8,
     jailtime: u32,
     remand: Mutex<HashMap<IpAddr, (u8, u64)>>,
 }
 
-fn exec(program: &str, cmd: &str, err: &str) -> Result<(), Error> {
+pub fn exec(program: &str, cmd: &str, err: &str) -> Result<(), Error> {
     let sentence_sl: Vec<&str> = cmd.split_whitespace().collect();
     let out = Command::new(program).args(sentence_sl).output()?;
     let sc = out.status.code();
@@ -41,16 +46,34 @@ impl Jail {
 
         log!("jail setup, allowance {}, time {}s", allowance, jailtime);
         Ok(Jail {
-            name: n,
+            backend: Backend::Ipset(n),
             allowance,
             jailtime,
             remand: Mutex::new(HashMap::new()),
         })
     }
 
-    pub fn sentence(&self, ip: IpAddr) -> Result<bool> {
-        let now = get_epoch();
+    // same logic, but bans are left to the caller, e.g. to report on
+    pub fn in_memory(allowance: u8, jailtime: u32) -> Jail {
+        Jail {
+            backend: Backend::Memory,
+            allowance,
+            jailtime,
+            remand: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // offences past the jailtime window are irrelevant
+    pub fn is_expired(&self, ts: u64) -> bool {
+        ts + (self.jailtime as u64) < get_epoch()
+    }
+
+    pub fn sentence(&self, ip: IpAddr, now: u64) -> Result<bool> {
+        self.sentence_with(ip, now, self.allowance)
+    }
 
+    // same, with a per-ip allowance, e.g. from geoip policies
+    pub fn sentence_with(&self, ip: IpAddr, now: u64, allowance: u8) -> Result<bool> {
         let should_ban = {
             let mut locked_map = self.remand.lock().map_err(|_| anyhow!("cant lock"))?;
 
@@ -66,7 +89,7 @@ impl Jail {
                     }
                 })
                 .or_insert((1, now));
-            if hits < self.allowance {
+            if hits < allowance {
                 false
             } else {
                 locked_map.remove_entry(&ip);
@@ -75,11 +98,18 @@ impl Jail {
         };
 
         if should_ban {
-            let cmd = format!("add -exist {} {}", self.name, ip);
-            exec("ipset", &cmd, "")?;
+            self.ban(ip)?;
             return Ok(true);
         }
 
         Ok(false)
     }
+
+    pub fn ban(&self, ip: IpAddr) -> Result<()> {
+        if let Backend::Ipset(name) = &self.backend {
+            let cmd = format!("add -exist {} {}", name, ip);
+            exec("ipset", &cmd, "")?;
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,289 @@
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::result::Result::Ok;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::*;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::mpsc::Sender;
+
+use crate::input::Entry;
+use crate::parsers::Kind;
+use crate::utils::log;
+
+// how often the cursor is persisted
+const CURSOR_INTERVAL: Duration = Duration::from_secs(5);
+
+// wait before restarting journalctl
+const RESPAWN_DELAY: Duration = Duration::from_secs(5);
+
+/// Journal entries to parse, e.g. sshd=_SYSTEMD_UNIT=ssh.service
+#[derive(Clone, Debug)]
+pub struct JournalMatch {
+    pub kind: Kind,
+    pub field: String,
+    pub value: String,
+}
+
+pub fn parse_match(a: &str) -> Result<JournalMatch> {
+    let mut split = a.splitn(3, '=');
+    let (kind, field, value) = match (split.next(), split.next(), split.next()) {
+        (Some(k), Some(f), Some(v)) if !f.is_empty() && !v.is_empty() => (k, f, v),
+        _ => bail!(
+            "invalid journal source {:?}, expected parser=FIELD=value",
+            a
+        ),
+    };
+
+    Ok(JournalMatch {
+        kind: Kind::from_str(kind)?,
+        field: field.to_string(),
+        value: value.to_string(),
+    })
+}
+
+fn field<'a>(json: &'a serde_json::Value, name: &str) -> Option<&'a str> {
+    json.get(name).and_then(|v| v.as_str())
+}
+
+// entry for the first matching source, and the journal cursor
+fn parse_entry(line: &str, matches: &[JournalMatch]) -> Result<(Option<Entry>, Option<String>)> {
+    let json: serde_json::Value = serde_json::from_str(line)?;
+    let cursor = field(&json, "__CURSOR").map(|c| c.to_string());
+
+    let kind = matches
+        .iter()
+        .find(|m| field(&json, &m.field) == Some(m.value.as_str()))
+        .map(|m| m.kind);
+
+    let kind = match kind {
+        Some(kind) => kind,
+        None => return Ok((None, cursor)),
+    };
+
+    // non utf-8 messages are serialized as an array of bytes
+    let line = match json.get("MESSAGE") {
+        Some(serde_json::Value::String(m)) => m.to_string(),
+        Some(serde_json::Value::Array(bytes)) => {
+            let bytes: Vec<u8> = bytes
+                .iter()
+                .filter_map(|b| b.as_u64())
+                .map(|b| b as u8)
+                .collect();
+            String::from_utf8_lossy(&bytes).to_string()
+        }
+        _ => return Ok((None, cursor)),
+    };
+
+    // microseconds
+    let ts = field(&json, "__REALTIME_TIMESTAMP")
+        .and_then(|t| t.parse::<u64>().ok())
+        .map(|t| t / 1_000_000);
+
+    let entry = Entry {
+        kind,
+        line,
+        ts,
+        cursor: cursor.clone(),
+    };
+    Ok((Some(entry), cursor))
+}
+
+/// Journal position of the last assessed entry, persisted on a timer and on shutdown
+pub struct Cursor {
+    path: PathBuf,
+    assessed: Mutex<Option<String>>,
+    saved: Mutex<Option<String>>,
+}
+
+impl Cursor {
+    pub fn new(path: PathBuf) -> Cursor {
+        let saved = std::fs::read_to_string(&path)
+            .ok()
+            .map(|c| c.trim().to_string())
+            .filter(|c| !c.is_empty());
+        Cursor {
+            path,
+            assessed: Mutex::new(saved.clone()),
+            saved: Mutex::new(saved),
+        }
+    }
+
+    fn get(&self) -> Option<String> {
+        self.assessed.lock().ok().and_then(|c| c.clone())
+    }
+
+    pub fn set(&self, cursor: String) -> Result<()> {
+        *self.assessed.lock().map_err(|_| anyhow!("cant lock"))? = Some(cursor);
+        Ok(())
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let cursor = self.get();
+        let mut saved = self.saved.lock().map_err(|_| anyhow!("cant lock"))?;
+        if let Some(c) = &cursor {
+            if cursor != *saved {
+                std::fs::write(&self.path, c).context("cant write journal cursor")?;
+                *saved = cursor;
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves every few seconds, when it moved
+    pub async fn persist(self: Arc<Self>) {
+        loop {
+            tokio::time::sleep(CURSOR_INTERVAL).await;
+            if let Err(e) = self.save() {
+                log!("ERR: {:?}", e);
+            }
+        }
+    }
+}
+
+// runs journalctl once, from `after`, and returns the cursor of the last entry read
+async fn run(
+    matches: &[JournalMatch],
+    after: Option<&str>,
+    since: Option<u64>,
+    tx: &Sender<Entry>,
+) -> Result<Option<String>> {
+    let mut cmd = Command::new("journalctl");
+    cmd.args(["--follow", "--output=json", "--no-pager"]);
+
+    // resume where we left, catch up on a window, or start at the end like files
+    match (after, since) {
+        (Some(c), _) => cmd.arg(format!("--after-cursor={}", c)),
+        (None, Some(since)) => cmd.arg(format!("--since=@{}", since)),
+        (None, None) => cmd.arg("--lines=0"),
+    };
+
+    // matches on the same field are ORed by journalctl, use + to OR across fields
+    for (i, m) in matches.iter().enumerate() {
+        if i > 0 {
+            cmd.arg("+");
+        }
+        cmd.arg(format!("{}={}", m.field, m.value));
+    }
+
+    let mut child = cmd
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .context("cant run journalctl, is it installed ?")?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| anyhow!("no journalctl stdout"))?;
+
+    let mut last = after.map(|c| c.to_string());
+    let mut lines = BufReader::new(stdout).lines();
+    while let Some(line) = lines.next_line().await? {
+        let (entry, cursor) = match parse_entry(&line, matches) {
+            Ok(ret) => ret,
+            Err(e) => {
+                log!("ERR: skipping journal entry {:?}", e);
+                continue;
+            }
+        };
+        if let Some(entry) = entry {
+            tx.send(entry).await?;
+        }
+        if cursor.is_some() {
+            last = cursor;
+        }
+    }
+
+    log!("journalctl exited, {:?}", child.wait().await?);
+    Ok(last)
+}
+
+/// Follows the journal, journalctl is restarted from where it was if it exits
+pub async fn follow(
+    matches: Vec<JournalMatch>,
+    cursor: Option<Arc<Cursor>>,
+    mut since: Option<u64>,
+    tx: Sender<Entry>,
+) -> Result<()> {
+    let mut after = cursor.as_ref().and_then(|c| c.get());
+    loop {
+        after = run(&matches, after.as_deref(), since, &tx).await?;
+        // caught up already
+        since = None;
+        tokio::time::sleep(RESPAWN_DELAY).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+
+    const ENTRY: &str = r#"{"__CURSOR":"s=739ad463348b4ceca5a9e69c95a3c93f;i=4ece7;b=6c7c6013a8674e2e93a6b1b1a0b1c9b3;m=1c8c8e0f0;t=5cc56e1e2f3a4;x=e1e2c5ac0a6a6a3d","__REALTIME_TIMESTAMP":"1632637519000000","_SYSTEMD_UNIT":"ssh.service","SYSLOG_IDENTIFIER":"sshd","MESSAGE":"Failed password for root from 179.124.36.195 port 41883 ssh2"}"#;
+
+    #[test]
+    fn matches() {
+        let m = parse_match("sshd=_SYSTEMD_UNIT=ssh.service").unwrap();
+        assert_eq!(m.kind, Kind::Sshd);
+        assert_eq!(m.field, "_SYSTEMD_UNIT");
+        assert_eq!(m.value, "ssh.service");
+
+        assert!(parse_match("sshd=_SYSTEMD_UNIT").is_err());
+        assert!(parse_match("nope=_SYSTEMD_UNIT=ssh.service").is_err());
+    }
+
+    #[test]
+    fn entries() {
+        let matches = [
+            parse_match("clf=SYSLOG_IDENTIFIER=nginx").unwrap(),
+            parse_match("sshd=SYSLOG_IDENTIFIER=sshd").unwrap(),
+        ];
+
+        let (entry, cursor) = parse_entry(ENTRY, &matches).unwrap();
+        let entry = entry.unwrap();
+        assert_eq!(entry.kind, Kind::Sshd);
+        assert_eq!(
+            entry.line,
+            "Failed password for root from 179.124.36.195 port 41883 ssh2"
+        );
+        assert_eq!(entry.ts, Some(1632637519));
+        assert!(cursor.unwrap().starts_with("s=739ad"));
+
+        // binary message
+        let line = r#"{"SYSLOG_IDENTIFIER":"sshd","MESSAGE":[104,105,255]}"#;
+        let (entry, cursor) = parse_entry(line, &matches).unwrap();
+        assert_eq!(entry.unwrap().line, "hi\u{fffd}");
+        assert!(cursor.is_none());
+
+        // no matching source
+        let (entry, _) = parse_entry(ENTRY, &matches[..1]).unwrap();
+        assert!(entry.is_none());
+
+        assert!(parse_entry("{", &matches).is_err());
+    }
+
+    #[test]
+    fn cursors() {
+        let dir = TempDir::new("cursor");
+        let path = dir.0.join("cursor");
+        std::fs::write(&path, "s=1\n").unwrap();
+
+        let cursor = Cursor::new(path.clone());
+        assert_eq!(cursor.get().as_deref(), Some("s=1"));
+
+        // only assessed entries move it, and it's saved when asked
+        cursor.set("s=2".to_string()).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "s=1\n");
+        cursor.save().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "s=2");
+
+        std::fs::remove_file(&path).unwrap();
+        let cursor = Cursor::new(path.clone());
+        assert_eq!(cursor.get(), None);
+        cursor.save().unwrap();
+        assert!(!path.exists());
+    }
+}
@@ -1,28 +1,165 @@
-use crate::utils::ParsingStatus;
+use crate::condition::Condition;
+use crate::http::{self, HttpRequest, HttpRules};
+use crate::timestamp;
+use crate::utils::{Offence, ParsingStatus};
 use anyhow::*;
-use std::{net::IpAddr, str::FromStr};
+use std::result::Result::Ok;
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
 
-pub fn parse(line: &str, valid_statuses: &[u32]) -> Result<ParsingStatus> {
-    let json: serde_json::Value = serde_json::from_str(line)?;
+/// Where the fields are in the logged JSON objects, Caddy's layout by default
+pub struct Layout {
+    pub ip: String,
+    pub status: String,
+    pub uri: String,
+    pub user_agent: String,
+    pub ts: String,
+    /// when set, lines matching all of them are bad, instead of checking the http status
+    pub conditions: Vec<Condition>,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout {
+            ip: "request.remote_ip".to_string(),
+            status: "status".to_string(),
+            uri: "request.uri".to_string(),
+            user_agent: "request.headers.User-Agent.0".to_string(),
+            ts: "ts".to_string(),
+            conditions: vec![],
+        }
+    }
+}
+
+// dotted path, array indexes as numbers. Keys with dots (e.g. ECS flattened fields) are
+// tried before nested objects
+fn lookup<'a>(json: &'a serde_json::Value, path: &str) -> Option<&'a serde_json::Value> {
+    if path.is_empty() {
+        return Some(json);
+    }
+    if let Some(v) = json.get(path) {
+        return Some(v);
+    }
+
+    let mut split = path.len();
+    while let Some(i) = path[..split].rfind('.') {
+        let (key, rest) = (&path[..i], &path[i + 1..]);
+        let child = match json {
+            serde_json::Value::Array(a) => key.parse::<usize>().ok().and_then(|i| a.get(i)),
+            _ => json.get(key),
+        };
+        if let Some(v) = child.and_then(|c| lookup(c, rest)) {
+            return Some(v);
+        }
+        split = i;
+    }
 
-    let remote_ip = json
-        .get("request")
-        .and_then(|r| r.get("remote_ip"))
+    match json {
+        serde_json::Value::Array(a) => path.parse::<usize>().ok().and_then(|i| a.get(i)),
+        _ => None,
+    }
+}
+
+// scalars as text, for conditions and the fields logged as either numbers or strings
+fn text(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(s) => Some(s.to_string()),
+        serde_json::Value::Number(n) => Some(n.to_string()),
+        serde_json::Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+// ip, or ip:port as logged by many Go services
+fn ip(json: &serde_json::Value, layout: &Layout) -> Result<IpAddr> {
+    let raw = lookup(json, &layout.ip)
         .and_then(|r| r.as_str())
-        .and_then(|r| IpAddr::from_str(r).ok())
-        .ok_or_else(|| anyhow!("cant parse json line - remote_ip"))?;
+        .ok_or_else(|| anyhow!("cant parse json line - {}", layout.ip))?;
+    IpAddr::from_str(raw)
+        .or_else(|_| SocketAddr::from_str(raw).map(|s| s.ip()))
+        .map_err(|_| anyhow!("cant parse json line - {}", layout.ip))
+}
 
-    let status = json
-        .get("status")
-        .and_then(|r| r.as_u64())
-        .ok_or_else(|| anyhow!("cant parse json line - status"))?;
+// caddy logs unix time by default, or iso8601 if configured so
+fn ts(json: &serde_json::Value, layout: &Layout) -> Option<u64> {
+    lookup(json, &layout.ts).and_then(|r| {
+        r.as_f64()
+            .map(|t| t as u64)
+            .or_else(|| r.as_str().and_then(timestamp::any))
+    })
+}
+
+fn status(json: &serde_json::Value, layout: &Layout) -> Option<u32> {
+    lookup(json, &layout.status)
+        .and_then(text)
+        .and_then(|r| r.parse::<u32>().ok())
+}
 
-    let is_good_status = valid_statuses.iter().any(|s| s == &(status as u32));
-    if !is_good_status {
-        return Ok(ParsingStatus::BadEntry(remote_ip));
+// the status is only required by callers checking it
+fn fields(json: &serde_json::Value, layout: &Layout, status: u32) -> Result<HttpRequest> {
+    let path = lookup(json, &layout.uri)
+        .and_then(|r| r.as_str())
+        .map(|r| r.to_string());
+
+    let user_agent = lookup(json, &layout.user_agent)
+        .and_then(|r| r.as_str())
+        .map(|r| r.to_string());
+
+    Ok(HttpRequest {
+        ip: ip(json, layout)?,
+        path,
+        user_agent,
+        status,
+        ts: ts(json, layout),
+    })
+}
+
+pub fn request(line: &str, layout: &Layout) -> Result<HttpRequest> {
+    let json: serde_json::Value = serde_json::from_str(line)?;
+
+    let status =
+        status(&json, layout).ok_or_else(|| anyhow!("cant parse json line - {}", layout.status))?;
+
+    fields(&json, layout, status)
+}
+
+pub fn parse(
+    line: &str,
+    layout: &Layout,
+    valid_statuses: &[u32],
+    rules: &HttpRules,
+    source_ts: Option<u64>,
+) -> Result<ParsingStatus> {
+    if layout.conditions.is_empty() {
+        let mut req = request(line, layout)?;
+        req.ts = req.ts.or(source_ts);
+        return http::assess(&req, valid_statuses, rules);
     }
 
-    Ok(ParsingStatus::OkEntry)
+    // the conditions replace the status check, the http rules still apply to lines with an ip
+    let json: serde_json::Value = serde_json::from_str(line)?;
+    let ts = ts(&json, layout).or(source_ts);
+    if let Ok(mut req) = fields(&json, layout, status(&json, layout).unwrap_or_default()) {
+        req.ts = ts;
+        if let Some(status) = http::assess_rules(&req, rules)? {
+            return Ok(status);
+        }
+    }
+
+    let bad = layout
+        .conditions
+        .iter()
+        .all(|c| c.matches(lookup(&json, &c.field).and_then(text).as_deref()));
+    if !bad {
+        return Ok(ParsingStatus::OkEntry);
+    }
+
+    Ok(ParsingStatus::BadEntry(Offence::new(
+        ip(&json, layout)?,
+        ts,
+    )))
 }
 
 #[cfg(test)]
@@ -37,7 +174,14 @@ mod tests {
         ];
 
         vectors.iter().for_each(|e| {
-            let ret = parse(*e, &vec![200, 404]).unwrap();
+            let ret = parse(
+                e,
+                &Layout::default(),
+                &[200, 404],
+                &HttpRules::default(),
+                None,
+            )
+            .unwrap();
             match ret {
                 ParsingStatus::BadEntry(_) => {}
                 _ => panic!("bad parsing"),
@@ -53,7 +197,14 @@ mod tests {
         ];
 
         vectors.iter().for_each(|e| {
-            let ret = parse(*e, &vec![200, 404]).unwrap();
+            let ret = parse(
+                e,
+                &Layout::default(),
+                &[200, 404],
+                &HttpRules::default(),
+                None,
+            )
+            .unwrap();
             match ret {
                 ParsingStatus::OkEntry => {}
                 _ => panic!("bad parsing"),
@@ -68,8 +219,161 @@ mod tests {
         ];
 
         vectors.iter().for_each(|e| {
-            let ret = parse(*e, &vec![200, 404]);
+            let ret = parse(
+                e,
+                &Layout::default(),
+                &[200, 404],
+                &HttpRules::default(),
+                None,
+            );
             assert!(ret.is_err());
         })
     }
+
+    #[test]
+    fn ts() {
+        let line = r#"{"ts":1738064403.2176833,"request":{"remote_ip":"127.0.0.1"},"status":200}"#;
+        assert_eq!(
+            request(line, &Layout::default()).unwrap().ts,
+            Some(1738064403)
+        );
+
+        let line =
+            r#"{"ts":"2025-01-28T11:40:03.217Z","request":{"remote_ip":"127.0.0.1"},"status":200}"#;
+        assert_eq!(
+            request(line, &Layout::default()).unwrap().ts,
+            Some(1738064403)
+        );
+
+        let line = r#"{"request":{"remote_ip":"127.0.0.1"},"status":200}"#;
+        assert_eq!(request(line, &Layout::default()).unwrap().ts, None);
+    }
+
+    // lines without their time are rate limited on the time of their source
+    #[test]
+    fn source_time() {
+        let rate = crate::rate::RateLimiter::new(1, 10, &[]).unwrap();
+        let rules = HttpRules::default().with_rate_limit(rate, crate::utils::TimeSource::Event);
+        let line = r#"{"request":{"remote_ip":"127.0.0.1","uri":"/"},"status":200}"#;
+        let parse_at = |ts| parse(line, &Layout::default(), &[200], &rules, Some(ts)).unwrap();
+
+        assert!(matches!(parse_at(1738064403), ParsingStatus::OkEntry));
+        assert!(matches!(parse_at(1738064503), ParsingStatus::OkEntry));
+        assert!(matches!(parse_at(1738064503), ParsingStatus::InstantBan(_)));
+    }
+
+    #[test]
+    fn scanner() {
+        let vectors = [
+            r#"{"request":{"remote_ip":"127.0.0.1","uri":"/wp-login.php","headers":{"User-Agent":["Mozilla/5.0"]}},"status":200}"#,
+            r#"{"request":{"remote_ip":"127.0.0.1","uri":"/","headers":{"User-Agent":["masscan/1.3"]}},"status":200}"#,
+        ];
+
+        let rules = HttpRules::new(true, &[], &[], true).unwrap();
+        vectors.iter().for_each(|e| {
+            let ret = parse(e, &Layout::default(), &[200, 404], &rules, None).unwrap();
+            match ret {
+                ParsingStatus::InstantBan(_) => {}
+                _ => panic!("bad parsing"),
+            }
+        })
+    }
+
+    #[test]
+    fn paths() {
+        let json: serde_json::Value = serde_json::from_str(
+            r#"{"http.request.remote_ip":"10.0.0.1","client":{"address":"10.0.0.2"},"a":[{"b":"c"}],"x.y":{"z":1}}"#,
+        )
+        .unwrap();
+        assert_eq!(lookup(&json, "http.request.remote_ip").unwrap(), "10.0.0.1");
+        assert_eq!(lookup(&json, "client.address").unwrap(), "10.0.0.2");
+        assert_eq!(lookup(&json, "a.0.b").unwrap(), "c");
+        assert_eq!(lookup(&json, "x.y.z").unwrap(), 1);
+        assert!(lookup(&json, "client.port").is_none());
+        assert!(lookup(&json, "a.1.b").is_none());
+    }
+
+    #[test]
+    fn layouts() {
+        let layout = Layout {
+            ip: "remote_addr".to_string(),
+            status: "code".to_string(),
+            ts: "time".to_string(),
+            ..Layout::default()
+        };
+        let line =
+            r#"{"time":"2021-09-26T06:25:19Z","remote_addr":"179.124.36.195:41883","code":"401"}"#;
+        match parse(line, &layout, &[200, 404], &HttpRules::default(), None).unwrap() {
+            ParsingStatus::BadEntry(o) => {
+                assert_eq!(o.ip, "179.124.36.195".parse::<IpAddr>().unwrap());
+                assert_eq!(o.ts, Some(1632637519));
+            }
+            _ => panic!("bad parsing"),
+        }
+    }
+
+    #[test]
+    fn conditions() {
+        use crate::condition::parse_condition;
+
+        let layout = Layout {
+            ip: "client.address".to_string(),
+            ts: "@timestamp".to_string(),
+            conditions: vec![
+                parse_condition("event.outcome=failure").unwrap(),
+                parse_condition("event.category~^auth").unwrap(),
+            ],
+            ..Layout::default()
+        };
+
+        let line = r#"{"@timestamp":"2021-09-26T06:25:19Z","client":{"address":"179.124.36.195"},"event":{"outcome":"failure","category":"authentication"}}"#;
+        match parse(line, &layout, &[], &HttpRules::default(), None).unwrap() {
+            ParsingStatus::BadEntry(o) => assert_eq!(o.ts, Some(1632637519)),
+            _ => panic!("bad parsing"),
+        }
+
+        let line = r#"{"client":{"address":"179.124.36.195"},"event":{"outcome":"success","category":"authentication"}}"#;
+        match parse(line, &layout, &[], &HttpRules::default(), None).unwrap() {
+            ParsingStatus::OkEntry => {}
+            _ => panic!("bad parsing"),
+        }
+
+        // no status needed, only the ip of bad lines
+        let line = r#"{"event":{"outcome":"failure","category":"authentication"}}"#;
+        assert!(parse(line, &layout, &[], &HttpRules::default(), None).is_err());
+
+        let layout = Layout {
+            conditions: vec![parse_condition("level=40..50").unwrap()],
+            ..Layout::default()
+        };
+        let line = r#"{"level":50,"request":{"remote_ip":"179.124.36.195"}}"#;
+        match parse(line, &layout, &[], &HttpRules::default(), None).unwrap() {
+            ParsingStatus::BadEntry(_) => {}
+            _ => panic!("bad parsing"),
+        }
+    }
+
+    #[test]
+    fn conditions_with_http_rules() {
+        use crate::condition::parse_condition;
+
+        let layout = Layout {
+            conditions: vec![parse_condition("level=40..50").unwrap()],
+            ..Layout::default()
+        };
+        let rules = HttpRules::new(true, &[], &[], true).unwrap();
+
+        // scanner paths are caught even when the conditions don't match
+        let line = r#"{"level":20,"request":{"remote_ip":"179.124.36.195","uri":"/.env"}}"#;
+        match parse(line, &layout, &[], &rules, None).unwrap() {
+            ParsingStatus::InstantBan(_) => {}
+            _ => panic!("bad parsing"),
+        }
+
+        let line = r#"{"level":20,"request":{"remote_ip":"179.124.36.195","uri":"/"}}"#;
+        match parse(line, &layout, &[], &rules, None).unwrap() {
+            ParsingStatus::OkEntry => {}
+            _ => panic!("bad parsing"),
+        }
+    }
 }
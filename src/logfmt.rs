@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use anyhow::*;
+
+use crate::condition::Condition;
+use crate::timestamp;
+use crate::utils::{Offence, ParsingStatus};
+
+pub struct LogfmtRules {
+    ip: String,
+    ts: String,
+    user: Option<String>,
+    conditions: Vec<Condition>,
+}
+
+impl LogfmtRules {
+    pub fn new(
+        ip: &str,
+        ts: &str,
+        user: Option<&str>,
+        conditions: &[Condition],
+    ) -> Result<LogfmtRules> {
+        if conditions.is_empty() {
+            bail!("logfmt parser requires a match condition");
+        }
+
+        Ok(LogfmtRules {
+            ip: ip.to_string(),
+            ts: ts.to_string(),
+            user: user.map(|u| u.to_string()),
+            conditions: conditions.to_vec(),
+        })
+    }
+}
+
+/// key=value pairs, values optionally double quoted with backslash escapes. A key
+/// without value is empty
+pub fn pairs(line: &str) -> HashMap<&str, String> {
+    let mut pairs = HashMap::new();
+    let mut rest = line.trim_start();
+
+    while !rest.is_empty() {
+        let key_end = rest.find([' ', '=']).unwrap_or(rest.len());
+        let key = &rest[..key_end];
+        rest = &rest[key_end..];
+
+        let mut value = String::new();
+        if let Some(after) = rest.strip_prefix('=') {
+            if let Some(quoted) = after.strip_prefix('"') {
+                let mut chars = quoted.char_indices();
+                let mut end = quoted.len();
+                while let Some((i, c)) = chars.next() {
+                    match c {
+                        '\\' => match chars.next() {
+                            Some((_, 'n')) => value.push('\n'),
+                            Some((_, 't')) => value.push('\t'),
+                            Some((_, c)) => value.push(c),
+                            None => {}
+                        },
+                        '"' => {
+                            end = i + 1;
+                            break;
+                        }
+                        c => value.push(c),
+                    }
+                }
+                rest = &quoted[end..];
+            } else {
+                let end = after.find(' ').unwrap_or(after.len());
+                value.push_str(&after[..end]);
+                rest = &after[end..];
+            }
+        }
+
+        if !key.is_empty() {
+            pairs.insert(key, value);
+        }
+        rest = rest.trim_start();
+    }
+    pairs
+}
+
+pub fn parse(line: &str, rules: &LogfmtRules) -> Result<ParsingStatus> {
+    let pairs = pairs(line);
+
+    let bad = rules
+        .conditions
+        .iter()
+        .all(|c| c.matches(pairs.get(c.field.as_str()).map(|v| v.as_str())));
+    if !bad {
+        return Ok(ParsingStatus::OkEntry);
+    }
+
+    // ip, or ip:port as logged by many Go services
+    let raw = pairs
+        .get(rules.ip.as_str())
+        .ok_or_else(|| anyhow!("cant parse logfmt line - {}", rules.ip))?;
+    let ip = IpAddr::from_str(raw)
+        .or_else(|_| SocketAddr::from_str(raw).map(|s| s.ip()))
+        .map_err(|_| anyhow!("cant parse logfmt line - {}", rules.ip))?;
+
+    let ts = pairs.get(rules.ts.as_str()).and_then(|t| timestamp::any(t));
+    let user = rules
+        .user
+        .as_ref()
+        .and_then(|u| pairs.get(u.as_str()))
+        .filter(|u| !u.is_empty())
+        .cloned();
+
+    Ok(ParsingStatus::BadEntry(
+        Offence::new(ip, ts).with_user(user),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::condition::parse_condition;
+
+    fn rules(conditions: &[&str]) -> LogfmtRules {
+        let conditions: Vec<Condition> = conditions
+            .iter()
+            .map(|c| parse_condition(c).unwrap())
+            .collect();
+        LogfmtRules::new("remote_addr", "t", Some("user"), &conditions).unwrap()
+    }
+
+    #[test]
+    fn pairs_parsing() {
+        let p = pairs(
+            r#"t=2021-09-26T06:25:19Z level=warn msg="login failed, \"bad\" password" empty= flag remote_addr=179.124.36.195:41883"#,
+        );
+        assert_eq!(p["t"], "2021-09-26T06:25:19Z");
+        assert_eq!(p["msg"], r#"login failed, "bad" password"#);
+        assert_eq!(p["empty"], "");
+        assert_eq!(p["flag"], "");
+        assert_eq!(p["remote_addr"], "179.124.36.195:41883");
+
+        // unterminated quote
+        let p = pairs(r#"msg="login failed"#);
+        assert_eq!(p["msg"], "login failed");
+    }
+
+    #[test]
+    fn positive() {
+        let rules = rules(&["level=warn", "msg~(?i)login failed"]);
+        let vectors = [
+            r#"t=2021-09-26T06:25:19Z level=warn msg="Login failed" user=admin remote_addr=179.124.36.195:41883"#,
+            r#"level=warn msg="login failed for user" remote_addr=2001:db8::1"#,
+            r#"level=warn msg="login failed" remote_addr=[2001:db8::1]:41883"#,
+        ];
+
+        vectors.iter().for_each(|e| {
+            let ret = parse(e, &rules).unwrap();
+            match ret {
+                ParsingStatus::BadEntry(_) => {}
+                _ => panic!("bad parsing"),
+            }
+        });
+
+        match parse(vectors[0], &rules).unwrap() {
+            ParsingStatus::BadEntry(o) => {
+                assert_eq!(o.ip, "179.124.36.195".parse::<IpAddr>().unwrap());
+                assert_eq!(o.ts, Some(1632637519));
+                assert_eq!(o.user.as_deref(), Some("admin"));
+            }
+            _ => panic!("bad parsing"),
+        }
+    }
+
+    #[test]
+    fn negative() {
+        let rules = rules(&["level=warn", "msg~(?i)login failed"]);
+        let vectors = [
+            r#"level=info msg="login failed" remote_addr=179.124.36.195"#,
+            r#"level=warn msg="disk almost full" remote_addr=179.124.36.195"#,
+            r#"level=warn remote_addr=179.124.36.195"#,
+        ];
+
+        vectors.iter().for_each(|e| {
+            let ret = parse(e, &rules).unwrap();
+            match ret {
+                ParsingStatus::OkEntry => {}
+                _ => panic!("bad parsing"),
+            }
+        })
+    }
+
+    #[test]
+    fn malformed() {
+        let rules = rules(&["level=warn"]);
+        let vectors = [
+            r#"level=warn msg="login failed""#,
+            r#"level=warn remote_addr=scanner.example.net"#,
+        ];
+
+        vectors.iter().for_each(|e| {
+            parse(e, &rules).expect_err("");
+        });
+
+        assert!(LogfmtRules::new("remote_addr", "t", None, &[]).is_err());
+    }
+}
@@ -1,120 +1,415 @@
 use std::result::Result::Ok;
 
+use std::sync::Arc;
+
 use anyhow::*;
 use clap::Parser;
-use linemux::{Line, MuxedLines};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
 
+mod analyze;
+mod blocklist;
+mod catchup;
 mod clf;
+mod condition;
+mod dns;
+mod docker;
+mod dovecot;
+mod fail2ban;
+mod fcrdns;
+mod follow;
 mod generic;
+mod geoip;
+mod haproxy;
+mod honeypot;
+mod http;
+mod input;
+mod journal;
 mod json;
+mod logfmt;
+mod mysql;
+mod netfilter;
+mod openvpn;
+mod parsers;
+mod postfix;
+mod postgres;
+mod preset;
+mod proftpd;
+mod rate;
 mod sshd;
+mod syslog;
+mod timestamp;
+mod traefik;
 mod utils;
+mod vsftpd;
 
 mod jail;
+use crate::blocklist::Blocklist;
+use crate::dns::Resolution;
+use crate::fcrdns::Verifier;
+use crate::follow::Follower;
+use crate::geoip::GeoIp;
 use crate::jail::Jail;
+use crate::parsers::{Kind, Parsers};
 use crate::utils::*;
 
-async fn run() -> Result<()> {
-    let args = utils::Args::parse();
-    let mut ml = MuxedLines::new()?;
-
-    // HTTP statuses
-    let ok_statuses = args.valid_http_statuses.clone();
-    let ok_statuses_parsed = parse_statuses(&ok_statuses)?;
-    let ok_statuses_ref = ok_statuses_parsed.as_ref();
-
-    // generic parser
-    let generic_path = args.generic_logpath.as_ref();
-    let generic_ip_re = args.generic_ip.as_ref();
-    let generic_positive = args.generic_positive.as_ref();
-    let generic_negative = args.generic_negative.as_ref();
-    if args.generic_ip.is_some()
-        || args.generic_logpath.is_some()
-        || args.generic_positive.is_some()
-        || args.generic_negative.is_some()
-    {
-        if args.generic_ip.is_none() || args.generic_logpath.is_none() {
-            bail!("generic parser needs both ip regex and log file path");
-        }
-        if !(args.generic_positive.is_some() ^ args.generic_negative.is_some()) {
-            bail!("generic parser requires either a positive or a negative regex");
-        }
-        if let Some(p) = generic_path.as_ref() {
-            ml.add_file(&p).await?;
-            log!("starting with generic parsing at {:?}", &p);
-        }
+async fn watch(args: &Args) -> Result<()> {
+    // lines wait on nothing, those with a hostname being resolved are parsed again later
+    let parsers = Parsers::new(args, args.time_source, Resolution::Background)?;
+    if let Some(limit) = args.http_rate_limit {
+        log!(
+            "http rate limit, {} requests per {}s",
+            limit,
+            args.http_rate_window
+        );
     }
 
-    // sshd
-    let sshd_logpath = args.sshd_logpath.as_ref();
-    if let Some(p) = sshd_logpath {
-        ml.add_file(&p).await?;
-        log!("starting with sshd parsing at {:?}", &p);
+    // stdin and named pipes are read as they come, the rest is tailed
+    let (streams, sources): (Vec<_>, Vec<_>) = parsers::sources(args)
+        .into_iter()
+        .partition(|(_, p)| input::is_stream(p));
+    let stdin = streams
+        .iter()
+        .filter(|(_, p)| p.as_os_str() == input::STDIN);
+    if stdin.count() > 1 {
+        bail!("only one source can be read from stdin");
     }
 
-    // common log format
-    let clf_logpath = args.clf_logpath.as_ref();
-    if let Some(p) = clf_logpath {
-        ml.add_file(&p).await?;
-        log!("starting with clf parsing at {:?}", &p);
+    let syslog = args.syslog_udp.is_some() || args.syslog_tcp.is_some();
+    if syslog && args.syslog_route.is_empty() {
+        bail!("syslog listener needs at least one route, see --syslog-route");
     }
-
-    // json
-    let json_logpath = args.json_logpath.as_ref();
-    if let Some(p) = json_logpath {
-        ml.add_file(&p).await?;
-        log!("starting with json parsing at {:?}", &p);
+    // anyone reaching the port could get any ip banned
+    let exposed = [args.syslog_udp, args.syslog_tcp]
+        .iter()
+        .flatten()
+        .any(|a| !a.ip().is_loopback());
+    if exposed && args.syslog_allow.is_empty() {
+        log!("WARN: syslog listener open to any peer, see --syslog-allow");
+    }
+    if sources.is_empty()
+        && streams.is_empty()
+        && args.journal.is_empty()
+        && args.docker.is_empty()
+        && !syslog
+        && args.honeypot.is_empty()
+    {
+        bail!("no log sources to parse, see --help");
     }
 
-    if json_logpath.is_none() && clf_logpath.is_none() && sshd_logpath.is_none() {
-        bail!("no log files to parse, see --help");
+    let mut followers = vec![];
+    let mut lengths = vec![];
+    for (kind, p) in &sources {
+        let follower = Follower::new(p.clone(), false).await;
+        if !p.exists() {
+            log!("{:?} does not exist yet, waiting for it", &p);
+        }
+        // whatever is past this is read by the tailing
+        lengths.push(follower.position());
+        followers.push((*kind, follower));
+        log!("starting with {} parsing at {:?}", kind, &p);
     }
 
     // jail
     let jail = Jail::new(args.allowance, args.jailtime)?;
 
-    let assess_line = |line: Line| {
-        let payload = line.line();
-        let path_buf = Some(line.source().to_path_buf());
-        let path = path_buf.as_ref();
-
-        let (target, ret) = if path == sshd_logpath {
-            ("sshd", sshd::parse(payload)?)
-        } else if path == clf_logpath {
-            ("clf", clf::parse(payload, ok_statuses_ref)?)
-        } else if path == json_logpath {
-            ("json", json::parse(payload, ok_statuses_ref)?)
-        } else if path == generic_path {
-            (
-                "generic",
-                generic::parse(payload, generic_ip_re, generic_positive, generic_negative)?,
-            )
-        } else {
-            bail!("file {:?} unknown ?", path)
+    // static blocklists, in their own permanent set
+    if !args.blocklist.is_empty() {
+        let blocklist = Blocklist::new(args.blocklist.clone())?;
+        tokio::spawn(blocklist.watch(args.blocklist_refresh));
+    }
+
+    // geoip enrichment and allowances
+    let geoip = GeoIp::from_args(args)?;
+
+    // verified crawlers on http sources
+    let crawlers = match (args.http_allow_rdns.is_empty(), args.resolver) {
+        (true, _) => None,
+        (false, Some(server)) => Some(Verifier::new(&args.http_allow_rdns, server)),
+        (false, None) => bail!("rdns allow rules need a dns server, see --resolver"),
+    };
+
+    let sentence = |kind: Kind,
+                    ret: ParsingStatus,
+                    source_ts: Option<u64>,
+                    time_source: TimeSource|
+     -> Result<()> {
+        let (offence, instant) = match ret {
+            ParsingStatus::BadEntry(o) => (o, false),
+            ParsingStatus::InstantBan(o) => (o, true),
+            ParsingStatus::OkEntry => return Ok(()),
         };
 
-        if let ParsingStatus::BadEntry(ip) = ret {
+        let ip = offence.ip;
+        let ts = time_source.pick(offence.ts.or(source_ts));
+        if jail.is_expired(ts) {
             if args.verbose {
-                log!("{} logged offence for {}", target, ip);
+                log!("{} ignored stale offence for {} at {}", kind, offence, ts);
             }
-            let banned = jail.sentence(ip)?;
-            if banned {
-                log!("{} jailtime for {}", target, ip);
+            return Ok(());
+        }
+
+        if let Some(crawlers) = crawlers.as_ref().filter(|_| kind.is_http()) {
+            if crawlers.is_allowed(ip, get_epoch()) {
+                if args.verbose {
+                    log!("{} allowed verified crawler {}", kind, offence);
+                }
+                return Ok(());
             }
         }
 
+        let geo = geoip.as_ref().map(|g| g.lookup(ip));
+        let (allowance, instant) = match (&geoip, &geo) {
+            (Some(g), Some(geo)) => (
+                g.allowance(geo, args.allowance),
+                instant && g.allows_instant(geo),
+            ),
+            _ => (args.allowance, instant),
+        };
+        let offence = offence.with_geo(geo);
+
+        if instant {
+            jail.ban(ip)?;
+            log!("{} instant jailtime for {}", kind, offence);
+            return Ok(());
+        }
+
+        if args.verbose {
+            log!("{} logged offence for {}", kind, offence);
+        }
+        let banned = jail.sentence_with(ip, ts, allowance)?;
+        if banned {
+            log!("{} jailtime for {}", kind, offence);
+        }
+
         Ok(())
     };
 
-    while let Ok(Some(line)) = ml.next_line().await {
-        if let Err(e) = assess_line(line) {
-            log!("ERR: {:?}", e);
+    // catch-up on what was logged before starting, on event time as the lines are replayed at once
+    if let Some(window) = args.catch_up {
+        let catch_up_parsers = Parsers::new(args, TimeSource::Event, Resolution::Blocking)?;
+        let since = get_epoch().saturating_sub(window);
+        for ((kind, p), len) in sources.iter().zip(lengths) {
+            // catching up is best effort, the tailing goes on regardless
+            let offences = match catchup::read(p, len, since, *kind, &catch_up_parsers) {
+                Ok(offences) => offences,
+                Err(e) => {
+                    log!("ERR: {} catching up at {:?} {:?}", kind, p, e);
+                    continue;
+                }
+            };
+            log!(
+                "{} caught up on {} offences at {:?}",
+                kind,
+                offences.len(),
+                p
+            );
+            for ret in offences {
+                sentence(*kind, ret, None, TimeSource::Event)?;
+            }
+        }
+    }
+
+    // every source feeds the same channel
+    let (tx, mut rx) = mpsc::channel(1024);
+
+    for (kind, follower) in followers {
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            if let Err(e) = input::tail(kind, follower, tx).await {
+                log!("ERR: tailing file {:?}", e);
+            }
+        });
+    }
+
+    for (kind, p) in streams {
+        log!("starting with {} parsing at {:?}", kind, &p);
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            if let Err(e) = input::read_stream(kind, p, tx).await {
+                log!("ERR: reading stream {:?}", e);
+            }
+        });
+    }
+
+    let journal_cursor = args
+        .journal_cursor
+        .clone()
+        .map(|p| Arc::new(journal::Cursor::new(p)));
+    if let Some(cursor) = journal_cursor.clone() {
+        tokio::spawn(cursor.persist());
+    }
+
+    if !args.journal.is_empty() {
+        for m in &args.journal {
+            parsers.ensure(m.kind)?;
+            log!(
+                "starting with {} parsing on journal {}={}",
+                m.kind,
+                m.field,
+                m.value
+            );
+        }
+        let tx = tx.clone();
+        let matches = args.journal.clone();
+        let cursor = journal_cursor.clone();
+        let since = args.catch_up.map(|w| get_epoch().saturating_sub(w));
+        tokio::spawn(async move {
+            if let Err(e) = journal::follow(matches, cursor, since, tx).await {
+                log!("ERR: following journal {:?}", e);
+            }
+        });
+    }
+
+    if !args.docker.is_empty() {
+        for d in &args.docker {
+            parsers.ensure(d.kind)?;
+        }
+        let tx = tx.clone();
+        let sources = args.docker.clone();
+        let root = args.docker_root.clone();
+        tokio::spawn(async move {
+            if let Err(e) = docker::follow(sources, root, tx).await {
+                log!("ERR: following docker containers {:?}", e);
+            }
+        });
+    }
+
+    for r in &args.syslog_route {
+        parsers.ensure(r.kind)?;
+        log!("starting with {} parsing on syslog app {}", r.kind, r.app);
+    }
+
+    if let Some(addr) = args.syslog_udp {
+        let tx = tx.clone();
+        let routes = args.syslog_route.clone();
+        let allow = args.syslog_allow.clone();
+        tokio::spawn(async move {
+            if let Err(e) = syslog::listen_udp(addr, routes, allow, tx).await {
+                log!("ERR: syslog udp listener {:?}", e);
+            }
+        });
+        log!("listening for syslog on udp {}", addr);
+    }
+
+    if let Some(addr) = args.syslog_tcp {
+        let tx = tx.clone();
+        let routes = args.syslog_route.clone();
+        let allow = args.syslog_allow.clone();
+        tokio::spawn(async move {
+            if let Err(e) = syslog::listen_tcp(addr, routes, allow, tx).await {
+                log!("ERR: syslog tcp listener {:?}", e);
+            }
+        });
+        log!("listening for syslog on tcp {}", addr);
+    }
+
+    // honeypot offences skip the parsers
+    let (honeypot_tx, mut honeypot_rx) = mpsc::channel(1024);
+    for addr in args.honeypot.clone() {
+        let tx = honeypot_tx.clone();
+        let tarpit = args.honeypot_tarpit;
+        let instant_ban = args.honeypot_instant_ban;
+        tokio::spawn(async move {
+            if let Err(e) = honeypot::listen(addr, tarpit, instant_ban, tx).await {
+                log!("ERR: honeypot listener {:?}", e);
+            }
+        });
+        log!("listening as honeypot on tcp {}", addr);
+    }
+    drop(tx);
+    drop(honeypot_tx);
+
+    // lines waiting on a hostname, parsed again once
+    let (retry_tx, mut retry_rx) = mpsc::channel(1024);
+
+    let assess_line = |entry: &input::Entry| -> Result<()> {
+        let ret = parsers.parse(entry.kind, &entry.line, entry.ts)?;
+        sentence(entry.kind, ret, entry.ts, args.time_source)
+    };
+
+    let shutdown = shutdown();
+    tokio::pin!(shutdown);
+    let (mut lines_open, mut honeypots_open) = (true, true);
+    while lines_open || honeypots_open {
+        let (entry, retried) = tokio::select! {
+            entry = rx.recv(), if lines_open => (entry, false),
+            Some(entry) = retry_rx.recv() => (Some(entry), true),
+            ret = honeypot_rx.recv(), if honeypots_open => {
+                match ret {
+                    Some(ret) => {
+                        if let Err(e) = sentence(Kind::Honeypot, ret, None, args.time_source) {
+                            log!("ERR: {:?}", e);
+                        }
+                    }
+                    None => honeypots_open = false,
+                }
+                continue;
+            }
+            _ = &mut shutdown => break,
+        };
+        let entry = match entry {
+            Some(entry) => entry,
+            None => {
+                lines_open = false;
+                continue;
+            }
+        };
+
+        // a retried line's position was already passed
+        let cursor = entry.cursor.clone().filter(|_| !retried);
+        match assess_line(&entry) {
+            Ok(()) => {}
+            Err(e) if !retried && dns::is_pending(&e) => {
+                let tx = retry_tx.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(dns::PENDING_RETRY).await;
+                    let _ = tx.send(entry).await;
+                });
+            }
+            Err(e) => {
+                log!("ERR: {:?}", e);
+            }
+        }
+        // like the persist timer, a cursor not recorded doesn't stop the banning
+        if let (Some(saved), Some(cursor)) = (&journal_cursor, cursor) {
+            if let Err(e) = saved.set(cursor) {
+                log!("ERR: {:?}", e);
+            }
         }
     }
 
+    if let Some(cursor) = &journal_cursor {
+        cursor.save()?;
+    }
     Ok(())
 }
 
+// ctrl-c, or a SIGTERM from the service manager
+async fn shutdown() {
+    let mut term = match signal(SignalKind::terminate()) {
+        Ok(term) => term,
+        Err(e) => {
+            log!("ERR: cant listen for SIGTERM {:?}", e);
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = term.recv() => {}
+    }
+    log!("shutting down");
+}
+
+async fn run() -> Result<()> {
+    let cli = utils::Cli::parse();
+    match &cli.command {
+        Some(Command::Analyze(args)) => analyze::run(args),
+        None => watch(&cli.args).await,
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     run().await?;
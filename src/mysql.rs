@@ -0,0 +1,107 @@
+use anyhow::*;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{net::IpAddr, str::FromStr};
+
+use crate::timestamp;
+use crate::utils::{Offence, ParsingStatus};
+
+lazy_static! {
+    static ref RE_DENIED: Regex =
+        Regex::new(r"Access denied for user '([^']*)'@'([^']*)'").unwrap();
+    // MariaDB pads single digit hours with a space
+    static ref RE_MARIADB_TIME: Regex =
+        Regex::new(r"^(\d{4}-\d{2}-\d{2})\s+(\d{1,2}):(\d{2}:\d{2})\s").unwrap();
+}
+
+// MySQL logs RFC3339 in UTC, MariaDB local time
+fn time(line: &str) -> Option<u64> {
+    if let Some(ts) = timestamp::syslog(line) {
+        return Some(ts);
+    }
+    let c = RE_MARIADB_TIME.captures(line)?;
+    timestamp::any(&format!("{} {:0>2}:{}", &c[1], &c[2], &c[3]))
+}
+
+pub fn parse(line: &str) -> Result<ParsingStatus> {
+    let c = match RE_DENIED.captures(line) {
+        Some(c) => c,
+        None => return Ok(ParsingStatus::OkEntry),
+    };
+
+    // a hostname unless skip-name-resolve is set
+    let host = &c[2];
+    if host == "localhost" {
+        return Ok(ParsingStatus::OkEntry);
+    }
+    let ip = IpAddr::from_str(host)
+        .map_err(|_| anyhow!("cant parse mysql line - ip, is skip-name-resolve set ?"))?;
+
+    Ok(ParsingStatus::BadEntry(
+        Offence::new(ip, time(line)).with_user(Some(c[1].to_string())),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Local, TimeZone};
+
+    #[test]
+    fn positive() {
+        let vectors = [
+            "2021-09-26T06:25:19.123456Z 8 [Note] [MY-010926] [Server] Access denied for user 'root'@'179.124.36.195' (using password: YES)",
+            "2021-09-26  6:25:19 8 [Warning] Access denied for user 'admin'@'35.184.211.144' (using password: NO)",
+            "Sep 26 06:25:19 db mariadbd[812]: 2021-09-26  6:25:19 8 [Warning] Access denied for user ''@'2001:db8::1' (using password: YES)",
+        ];
+
+        vectors.iter().for_each(|e| {
+            let ret = parse(e).unwrap();
+            match ret {
+                ParsingStatus::BadEntry(_) => {}
+                _ => panic!("bad parsing"),
+            }
+        });
+
+        match parse(vectors[0]).unwrap() {
+            ParsingStatus::BadEntry(o) => {
+                assert_eq!(o.ts, Some(1632637519));
+                assert_eq!(o.user.as_deref(), Some("root"));
+            }
+            _ => panic!("bad parsing"),
+        }
+        let expected = Local.with_ymd_and_hms(2021, 9, 26, 6, 25, 19).unwrap();
+        match parse(vectors[1]).unwrap() {
+            ParsingStatus::BadEntry(o) => assert_eq!(o.ts, Some(expected.timestamp() as u64)),
+            _ => panic!("bad parsing"),
+        }
+    }
+
+    #[test]
+    fn negative() {
+        let vectors = [
+            "2021-09-26T06:25:19.123456Z 0 [System] [MY-010931] [Server] /usr/sbin/mysqld: ready for connections.",
+            "2021-09-26  6:25:19 8 [Warning] Access denied for user 'root'@'localhost' (using password: YES)",
+            "2021-09-26  6:25:19 8 [Warning] Aborted connection 8 to db: 'app' user: 'app' host: '10.0.0.3' (Got timeout reading communication packets)",
+        ];
+
+        vectors.iter().for_each(|e| {
+            let ret = parse(e).unwrap();
+            match ret {
+                ParsingStatus::OkEntry => {}
+                _ => panic!("bad parsing"),
+            }
+        })
+    }
+
+    #[test]
+    fn malformed() {
+        let vectors = [
+            "2021-09-26  6:25:19 8 [Warning] Access denied for user 'root'@'scanner.example.net' (using password: YES)",
+        ];
+
+        vectors.iter().for_each(|e| {
+            parse(e).expect_err("");
+        })
+    }
+}
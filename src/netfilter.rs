@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use anyhow::*;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::timestamp;
+use crate::utils::{Offence, ParsingStatus, TimeSource};
+
+// prune stale sources past this many tracked ips
+const MAX_SOURCES: usize = 10_000;
+
+lazy_static! {
+    // LOG and NFLOG (ulogd) lines share the same key=value fields
+    static ref RE_PACKET: Regex =
+        Regex::new(r"\bIN=(\S*) .*\bSRC=(\S+) .*\bPROTO=(\S+)").unwrap();
+    static ref RE_DPT: Regex = Regex::new(r"\bDPT=(\d+)\b").unwrap();
+}
+
+/// Counts the distinct destination ports each source ip hits within a window
+pub struct PortScan {
+    ports: u32,
+    window: u64,
+    udp: bool,
+    time_source: TimeSource,
+    probes: Mutex<HashMap<IpAddr, Vec<(u16, u64)>>>,
+    pruned: AtomicU64,
+}
+
+impl PortScan {
+    pub fn new(ports: u32, window: u32, time_source: TimeSource) -> Result<PortScan> {
+        ensure!(ports > 0, "netfilter port count should be above 0");
+        ensure!(window > 0, "netfilter window should be above 0");
+
+        Ok(PortScan {
+            ports,
+            window: window as u64,
+            udp: false,
+            time_source,
+            probes: Mutex::new(HashMap::new()),
+            pruned: AtomicU64::new(0),
+        })
+    }
+
+    /// Counts udp probes too, prone to false positives as replies to outgoing traffic and
+    /// spoofed sources look alike
+    pub fn with_udp(mut self) -> PortScan {
+        self.udp = true;
+        self
+    }
+
+    // returns true when the ip probed more than `ports` distinct ports within the window
+    pub fn hit(&self, ip: IpAddr, port: u16, now: u64) -> Result<bool> {
+        let since = now.saturating_sub(self.window);
+        let mut locked_map = self.probes.lock().map_err(|_| anyhow!("cant lock"))?;
+
+        let pruned = self.pruned.load(Ordering::Relaxed);
+        if locked_map.len() > MAX_SOURCES && now >= pruned + self.window {
+            self.pruned.store(now, Ordering::Relaxed);
+            locked_map.retain(|_, probes| probes.iter().any(|(_, ts)| *ts > since));
+        }
+
+        let probes = locked_map.entry(ip).or_default();
+        probes.retain(|(_, ts)| *ts > since);
+        match probes.iter_mut().find(|(p, _)| *p == port) {
+            Some(probe) => probe.1 = now,
+            None => probes.push((port, now)),
+        }
+
+        if probes.len() > self.ports as usize {
+            locked_map.remove(&ip);
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+}
+
+// single probes are expected noise, only scans are banned, right away. `source_ts` is the
+// time given by the source, if the line has none
+pub fn parse(line: &str, scan: &PortScan, source_ts: Option<u64>) -> Result<ParsingStatus> {
+    if !line.contains("SRC=") {
+        return Ok(ParsingStatus::OkEntry);
+    }
+
+    let c = RE_PACKET
+        .captures(line)
+        .ok_or_else(|| anyhow!("cant parse netfilter line - fields"))?;
+
+    // outgoing packets have no input interface
+    if c[1].is_empty() {
+        return Ok(ParsingStatus::OkEntry);
+    }
+
+    // icmp and the likes have no ports
+    if c[3] != *"TCP" && c[3] != *"UDP" {
+        return Ok(ParsingStatus::OkEntry);
+    }
+
+    let ip = IpAddr::from_str(&c[2]).map_err(|_| anyhow!("cant parse netfilter line - ip"))?;
+    let port = RE_DPT
+        .captures(line)
+        .and_then(|d| u16::from_str(&d[1]).ok())
+        .ok_or_else(|| anyhow!("cant parse netfilter line - port"))?;
+
+    // only connection attempts count, not replies to outgoing traffic nor late packets of
+    // closed connections
+    let probe = match &c[3] {
+        "TCP" => {
+            let flag = |f: &str| line.split_whitespace().any(|w| w == f);
+            flag("SYN") && !flag("ACK")
+        }
+        _ => scan.udp,
+    };
+    if !probe {
+        return Ok(ParsingStatus::OkEntry);
+    }
+
+    let ts = timestamp::syslog(line).or(source_ts);
+    if scan.hit(ip, port, scan.time_source.pick(ts))? {
+        return Ok(ParsingStatus::InstantBan(Offence::new(ip, ts)));
+    }
+
+    Ok(ParsingStatus::OkEntry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn probe(ip: &str, port: u16) -> String {
+        format!("Sep 26 06:25:19 fw kernel: [1234.567890] DROP: IN=eth0 OUT= MAC=00:16:3e:5e:6c:00:00:16:3e:6c:5e:00:08:00 SRC={} DST=10.0.0.2 LEN=44 TOS=0x00 PREC=0x00 TTL=243 ID=54321 PROTO=TCP SPT=51234 DPT={} WINDOW=1024 RES=0x00 SYN URGP=0", ip, port)
+    }
+
+    #[test]
+    fn positive() {
+        let scan = PortScan::new(3, 60, TimeSource::Event).unwrap();
+        for port in [23, 80, 3389] {
+            match parse(&probe("179.124.36.195", port), &scan, None).unwrap() {
+                ParsingStatus::OkEntry => {}
+                _ => panic!("bad parsing"),
+            }
+        }
+        match parse(&probe("179.124.36.195", 5900), &scan, None).unwrap() {
+            ParsingStatus::InstantBan(o) => {
+                assert_eq!(o.ip, "179.124.36.195".parse::<IpAddr>().unwrap())
+            }
+            _ => panic!("bad parsing"),
+        }
+
+        // NFLOG through ulogd, over IPv6, with udp opted in
+        let scan = PortScan::new(1, 60, TimeSource::Event).unwrap().with_udp();
+        let vectors = [
+            "Sep 26 06:25:19 fw ulogd[512]: [fw-drop] IN=eth0 OUT= MAC=00:16:3e:5e:6c:00 SRC=2001:db8::1 DST=2001:db8::2 LEN=80 TC=0 HOPLIMIT=54 FLOWLBL=0 PROTO=UDP SPT=40000 DPT=161 LEN=40",
+            "Sep 26 06:25:19 fw ulogd[512]: [fw-drop] IN=eth0 OUT= MAC=00:16:3e:5e:6c:00 SRC=2001:db8::1 DST=2001:db8::2 LEN=80 TC=0 HOPLIMIT=54 FLOWLBL=0 PROTO=UDP SPT=40000 DPT=1900 LEN=40",
+        ];
+        parse(vectors[0], &scan, None).unwrap();
+        match parse(vectors[1], &scan, None).unwrap() {
+            ParsingStatus::InstantBan(o) => {
+                assert_eq!(o.ip, "2001:db8::1".parse::<IpAddr>().unwrap())
+            }
+            _ => panic!("bad parsing"),
+        }
+    }
+
+    #[test]
+    fn negative() {
+        let scan = PortScan::new(1, 60, TimeSource::Event).unwrap();
+
+        // the same port over and over isn't a scan, nor are different sources
+        for _ in 0..5 {
+            parse(&probe("179.124.36.195", 22), &scan, None).unwrap();
+        }
+        let vectors = [
+            probe("179.124.36.195", 22),
+            probe("35.184.211.144", 80),
+            "Sep 26 06:25:19 fw kernel: DROP: IN=eth0 OUT= SRC=179.124.36.196 DST=10.0.0.2 LEN=84 PROTO=ICMP TYPE=8 CODE=0 ID=1 SEQ=1".to_string(),
+            "Sep 26 06:25:19 fw kernel: OUT-DROP: IN= OUT=eth0 SRC=10.0.0.2 DST=179.124.36.195 LEN=44 PROTO=TCP SPT=22 DPT=51234".to_string(),
+            "Sep 26 06:25:19 fw kernel: [1234.567890] eth0: link up".to_string(),
+        ];
+
+        vectors.iter().for_each(|e| {
+            let ret = parse(e, &scan, None).unwrap();
+            match ret {
+                ParsingStatus::OkEntry => {}
+                _ => panic!("bad parsing"),
+            }
+        });
+
+        // replies to outgoing traffic, late packets of closed connections, and udp unless
+        // opted in, never count
+        let scan = PortScan::new(1, 60, TimeSource::Event).unwrap();
+        let vectors = [
+            probe("179.124.36.195", 23).replace(" SYN ", " ACK SYN "),
+            probe("179.124.36.195", 25).replace(" SYN ", " ACK FIN "),
+            probe("179.124.36.195", 80).replace(" SYN ", " RST "),
+            "Sep 26 06:25:19 fw kernel: DROP: IN=eth0 OUT= SRC=179.124.36.195 DST=10.0.0.2 LEN=40 PROTO=UDP SPT=53 DPT=40000 LEN=20".to_string(),
+            "Sep 26 06:25:19 fw kernel: DROP: IN=eth0 OUT= SRC=179.124.36.195 DST=10.0.0.2 LEN=40 PROTO=UDP SPT=53 DPT=40001 LEN=20".to_string(),
+        ];
+
+        vectors.iter().for_each(|e| {
+            let ret = parse(e, &scan, None).unwrap();
+            match ret {
+                ParsingStatus::OkEntry => {}
+                _ => panic!("bad parsing"),
+            }
+        })
+    }
+
+    #[test]
+    fn malformed() {
+        let scan = PortScan::new(1, 60, TimeSource::Event).unwrap();
+        let vectors = [
+            probe("179.124.36.195.232", 23),
+            "Sep 26 06:25:19 fw kernel: DROP: IN=eth0 OUT= SRC=179.124.36.195 DST=10.0.0.2 LEN=44 PROTO=TCP SPT=51234".to_string(),
+            "Sep 26 06:25:19 fw kernel: DROP: SRC=179.124.36.195".to_string(),
+        ];
+
+        vectors.iter().for_each(|e| {
+            parse(e, &scan, None).expect_err("");
+        })
+    }
+
+    #[test]
+    fn window() {
+        let scan = PortScan::new(2, 60, TimeSource::Event).unwrap();
+        let ip = IpAddr::from_str("8.8.8.8").unwrap();
+
+        // slow scans stay under the radar
+        assert!(!scan.hit(ip, 1, 100).unwrap());
+        assert!(!scan.hit(ip, 2, 170).unwrap());
+        assert!(!scan.hit(ip, 3, 240).unwrap());
+
+        // fast ones don't
+        assert!(!scan.hit(ip, 4, 241).unwrap());
+        assert!(scan.hit(ip, 5, 242).unwrap());
+
+        // forgotten once banned
+        assert!(!scan.hit(ip, 6, 243).unwrap());
+    }
+
+    // journal and syslog lines carry their time next to the line, not in it
+    #[test]
+    fn source_time() {
+        let scan = PortScan::new(1, 60, TimeSource::Event).unwrap();
+        let probe = |port: u16| {
+            format!("DROP: IN=eth0 OUT= SRC=179.124.36.195 DST=10.0.0.2 LEN=44 PROTO=TCP SPT=51234 DPT={} SYN URGP=0", port)
+        };
+
+        // slow on event time, the last one fast
+        for (i, port) in [23, 80, 3389].iter().enumerate() {
+            let ts = 1632637519 + i as u64 * 100;
+            match parse(&probe(*port), &scan, Some(ts)).unwrap() {
+                ParsingStatus::OkEntry => {}
+                _ => panic!("bad parsing"),
+            }
+        }
+        match parse(&probe(5900), &scan, Some(1632637759)).unwrap() {
+            ParsingStatus::InstantBan(o) => assert_eq!(o.ts, Some(1632637759)),
+            _ => panic!("bad parsing"),
+        }
+    }
+
+    // stale sources are pruned at most once per window
+    #[test]
+    fn prune() {
+        let scan = PortScan::new(2, 60, TimeSource::Event).unwrap();
+        let ip = |i: usize| IpAddr::from([10, (i >> 16) as u8, (i >> 8) as u8, i as u8]);
+
+        for i in 0..=MAX_SOURCES {
+            scan.hit(ip(i), 1, 100).unwrap();
+        }
+        scan.hit(ip(MAX_SOURCES + 1), 1, 200).unwrap();
+        assert_eq!(scan.probes.lock().unwrap().len(), 1);
+
+        // stale again, but pruned just now
+        for i in 0..=MAX_SOURCES {
+            scan.hit(ip(i), 1, 100).unwrap();
+        }
+        scan.hit(ip(MAX_SOURCES + 1), 1, 230).unwrap();
+        assert_eq!(scan.probes.lock().unwrap().len(), MAX_SOURCES + 2);
+
+        scan.hit(ip(MAX_SOURCES + 2), 1, 260).unwrap();
+        assert_eq!(scan.probes.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,94 @@
+use anyhow::*;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{net::IpAddr, str::FromStr};
+
+use crate::timestamp;
+use crate::utils::{Offence, ParsingStatus};
+
+const OPENVPN_BAD: [&str; 3] = [
+    "TLS Error: TLS handshake failed",
+    "TLS Auth Error:",
+    "AUTH_FAILED",
+];
+
+lazy_static! {
+    // peer as ip:port, common-name/ip:port or [AF_INET]ip:port
+    static ref RE_PEER: Regex = Regex::new(
+        r"(?:^|\s)(?:\S*/)?(?:\[AF_INET6?\])?(?:::ffff:)?([0-9a-fA-F:.]+):\d+\b"
+    )
+    .unwrap();
+}
+
+pub fn parse(line: &str) -> Result<ParsingStatus> {
+    if !OPENVPN_BAD.iter().any(|bad| line.contains(bad)) {
+        return Ok(ParsingStatus::OkEntry);
+    }
+
+    let ip = RE_PEER
+        .captures_iter(line)
+        .filter_map(|c| IpAddr::from_str(&c[1]).ok())
+        .next()
+        .ok_or_else(|| anyhow!("cant parse openvpn line - ip"))?;
+
+    Ok(ParsingStatus::BadEntry(Offence::new(
+        ip,
+        timestamp::daemon(line),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positive() {
+        let vectors = [
+            "2021-09-26 06:25:19 179.124.36.195:41883 TLS Error: TLS handshake failed",
+            "Sep 26 06:25:19 vpn openvpn[4242]: 179.124.36.195:41883 TLS Error: TLS handshake failed",
+            "2021-09-26 06:25:19 us=123456 neal/35.184.211.144:1194 TLS Auth Error: Auth Username/Password verification failed for peer",
+            "Sun Sep 26 06:25:19 2021 [AF_INET6]2001:db8::1:1194 SENT CONTROL [neal]: 'AUTH_FAILED' (status=1)",
+        ];
+
+        vectors.iter().for_each(|e| {
+            let ret = parse(e).unwrap();
+            match ret {
+                ParsingStatus::BadEntry(_) => {}
+                _ => panic!("bad parsing"),
+            }
+        });
+
+        match parse(vectors[3]).unwrap() {
+            ParsingStatus::BadEntry(o) => {
+                assert_eq!(o.ip, "2001:db8::1".parse::<IpAddr>().unwrap());
+                assert!(o.ts.is_some());
+            }
+            _ => panic!("bad parsing"),
+        }
+    }
+
+    #[test]
+    fn negative() {
+        let vectors = [
+            "2021-09-26 06:25:19 179.124.36.195:41883 TLS: Initial packet from [AF_INET]179.124.36.195:41883",
+            "2021-09-26 06:25:19 neal/35.184.211.144:1194 MULTI_sva: pool returned IPv4=10.8.0.6",
+        ];
+
+        vectors.iter().for_each(|e| {
+            let ret = parse(e).unwrap();
+            match ret {
+                ParsingStatus::OkEntry => {}
+                _ => panic!("bad parsing"),
+            }
+        })
+    }
+
+    #[test]
+    fn malformed() {
+        let vectors = ["2021-09-26 06:25:19 TLS Error: TLS handshake failed"];
+
+        vectors.iter().for_each(|e| {
+            parse(e).expect_err("");
+        })
+    }
+}
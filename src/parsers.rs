@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::result::Result::Ok;
+use std::str::FromStr;
+
+use anyhow::*;
+
+use crate::dns::{Resolution, Resolver};
+use crate::generic::GenericRules;
+use crate::http::HttpRules;
+use crate::logfmt::{self, LogfmtRules};
+use crate::netfilter::{self, PortScan};
+use crate::preset::{self, Preset};
+use crate::rate::RateLimiter;
+use crate::utils::{parse_statuses, Args, ParsingStatus, TimeSource};
+use crate::{
+    clf, dovecot, fail2ban, generic, haproxy, json, mysql, openvpn, postfix, postgres, proftpd,
+    sshd, traefik, vsftpd,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Kind {
+    Sshd,
+    Clf,
+    Json,
+    Generic,
+    Postfix,
+    Dovecot,
+    Postgres,
+    Mysql,
+    Vsftpd,
+    Proftpd,
+    Openvpn,
+    Traefik,
+    Haproxy,
+    Logfmt,
+    Netfilter,
+    // connections to honeypot ports, not a parser sources can pick
+    Honeypot,
+    Preset(Preset),
+}
+
+impl FromStr for Kind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Kind> {
+        match s {
+            "sshd" => Ok(Kind::Sshd),
+            "clf" => Ok(Kind::Clf),
+            "json" => Ok(Kind::Json),
+            "generic" => Ok(Kind::Generic),
+            "postfix" => Ok(Kind::Postfix),
+            "dovecot" => Ok(Kind::Dovecot),
+            "postgres" => Ok(Kind::Postgres),
+            "mysql" => Ok(Kind::Mysql),
+            "vsftpd" => Ok(Kind::Vsftpd),
+            "proftpd" => Ok(Kind::Proftpd),
+            "openvpn" => Ok(Kind::Openvpn),
+            "traefik" => Ok(Kind::Traefik),
+            "haproxy" => Ok(Kind::Haproxy),
+            "logfmt" => Ok(Kind::Logfmt),
+            "netfilter" => Ok(Kind::Netfilter),
+            _ => Preset::from_str(s)
+                .map(Kind::Preset)
+                .map_err(|_| anyhow!("unknown parser {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Kind::Sshd => "sshd",
+            Kind::Clf => "clf",
+            Kind::Json => "json",
+            Kind::Generic => "generic",
+            Kind::Postfix => "postfix",
+            Kind::Dovecot => "dovecot",
+            Kind::Postgres => "postgres",
+            Kind::Mysql => "mysql",
+            Kind::Vsftpd => "vsftpd",
+            Kind::Proftpd => "proftpd",
+            Kind::Openvpn => "openvpn",
+            Kind::Traefik => "traefik",
+            Kind::Haproxy => "haproxy",
+            Kind::Logfmt => "logfmt",
+            Kind::Netfilter => "netfilter",
+            Kind::Honeypot => "honeypot",
+            Kind::Preset(preset) => return write!(f, "{}", preset),
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Kind {
+    /// Sources banning on http requests
+    pub fn is_http(&self) -> bool {
+        matches!(self, Kind::Clf | Kind::Json | Kind::Traefik | Kind::Haproxy)
+    }
+}
+
+pub struct Parsers {
+    ok_statuses: Vec<u32>,
+    http_rules: HttpRules,
+    generic: Option<GenericRules>,
+    json_layout: json::Layout,
+    logfmt: Option<LogfmtRules>,
+    postgres_prefix: postgres::Prefix,
+    port_scan: PortScan,
+    presets: HashMap<Preset, preset::Rules>,
+}
+
+impl Parsers {
+    pub fn new(args: &Args, time_source: TimeSource, resolution: Resolution) -> Result<Parsers> {
+        // HTTP statuses
+        let ok_statuses = parse_statuses(&args.valid_http_statuses)?;
+
+        // HTTP path and user-agent rules
+        let mut http_rules = HttpRules::new(
+            args.http_scanner_rules,
+            &args.http_bad_path,
+            &args.http_bad_user_agent,
+            args.http_instant_ban,
+        )?;
+
+        // HTTP rate limit
+        if let Some(limit) = args.http_rate_limit {
+            let rate = RateLimiter::new(limit, args.http_rate_window, &args.http_rate_exempt)?;
+            http_rules = http_rules.with_rate_limit(rate, time_source);
+        }
+
+        // generic parser, with the rules of fail2ban filters
+        let filters = args
+            .generic_fail2ban_filter
+            .iter()
+            .map(|path| fail2ban::load(path))
+            .collect::<Result<Vec<_>>>()?;
+
+        // plain texts are matched as is, next to the regexes
+        let texts = |texts: &[String], regexes: &[String]| -> Vec<String> {
+            texts
+                .iter()
+                .map(|t| regex::escape(t))
+                .chain(regexes.iter().cloned())
+                .collect()
+        };
+        let positive = texts(&args.generic_positive, &args.generic_positive_regex);
+        let negative = texts(&args.generic_negative, &args.generic_negative_regex);
+
+        let generic = if args.generic_ip.is_some() || !filters.is_empty() {
+            let mut rules = GenericRules::new(
+                args.generic_ip.clone().into_iter().collect(),
+                &positive,
+                &negative,
+                &filters,
+            )?;
+            // hostnames logged instead of ips
+            if args.hosts_file.is_some() || args.resolver.is_some() {
+                let resolver =
+                    Resolver::new(args.hosts_file.as_deref(), args.resolver, resolution)?;
+                rules = rules.with_resolver(resolver);
+            }
+            Some(rules)
+        } else if args.generic_logpath.is_some() || !positive.is_empty() || !negative.is_empty() {
+            bail!("generic parser needs an ip regex");
+        } else {
+            None
+        };
+
+        // netfilter port scans
+        let mut port_scan =
+            PortScan::new(args.netfilter_ports, args.netfilter_window, time_source)?;
+        if args.netfilter_udp {
+            port_scan = port_scan.with_udp();
+        }
+
+        // logfmt parser
+        let logfmt = if !args.logfmt_match.is_empty() {
+            Some(LogfmtRules::new(
+                &args.logfmt_ip_key,
+                &args.logfmt_time_key,
+                args.logfmt_user_key.as_deref(),
+                &args.logfmt_match,
+            )?)
+        } else if args.logfmt_logpath.is_some() {
+            bail!("logfmt parser requires a match condition");
+        } else {
+            None
+        };
+
+        Ok(Parsers {
+            ok_statuses,
+            http_rules,
+            generic,
+            json_layout: json::Layout {
+                ip: args.json_ip_path.clone(),
+                status: args.json_status_path.clone(),
+                ts: args.json_time_path.clone(),
+                conditions: args.json_match.clone(),
+                ..json::Layout::default()
+            },
+            logfmt,
+            postgres_prefix: postgres::Prefix::new(&args.postgres_log_prefix)?,
+            port_scan,
+            presets: preset::PRESETS
+                .iter()
+                .map(|p| Ok((*p, preset::Rules::new(*p)?)))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// Checks a parser is configured, for sources declaring their parser
+    pub fn ensure(&self, kind: Kind) -> Result<()> {
+        if kind == Kind::Generic && self.generic.is_none() {
+            bail!("generic parser needs an ip regex or a fail2ban filter, see --help");
+        }
+        if kind == Kind::Logfmt && self.logfmt.is_none() {
+            bail!("logfmt parser needs a match condition, see --help");
+        }
+        Ok(())
+    }
+
+    /// `source_ts` is the event time given by the source, for parsers keeping time windows
+    pub fn parse(&self, kind: Kind, line: &str, source_ts: Option<u64>) -> Result<ParsingStatus> {
+        match kind {
+            Kind::Sshd => sshd::parse(line),
+            Kind::Clf => clf::parse(line, &self.ok_statuses, &self.http_rules, source_ts),
+            Kind::Json => json::parse(
+                line,
+                &self.json_layout,
+                &self.ok_statuses,
+                &self.http_rules,
+                source_ts,
+            ),
+            Kind::Traefik => traefik::parse(line, &self.ok_statuses, &self.http_rules, source_ts),
+            Kind::Haproxy => haproxy::parse(line, &self.ok_statuses, &self.http_rules, source_ts),
+            Kind::Generic => match &self.generic {
+                Some(rules) => generic::parse(line, rules),
+                None => bail!("generic parser needs an ip regex or a fail2ban filter, see --help"),
+            },
+            Kind::Logfmt => match &self.logfmt {
+                Some(rules) => logfmt::parse(line, rules),
+                None => bail!("logfmt parser needs a match condition, see --help"),
+            },
+            Kind::Postfix => postfix::parse(line),
+            Kind::Dovecot => dovecot::parse(line),
+            Kind::Postgres => postgres::parse(line, &self.postgres_prefix),
+            Kind::Mysql => mysql::parse(line),
+            Kind::Vsftpd => vsftpd::parse(line),
+            Kind::Proftpd => proftpd::parse(line),
+            Kind::Openvpn => openvpn::parse(line),
+            Kind::Netfilter => netfilter::parse(line, &self.port_scan, source_ts),
+            Kind::Honeypot => bail!("honeypot connections aren't parsed from lines"),
+            Kind::Preset(p) => self.presets[&p].parse(line),
+        }
+    }
+}
+
+/// Log files to parse, along with their parser
+pub fn sources(args: &Args) -> Vec<(Kind, PathBuf)> {
+    [
+        (Kind::Sshd, &args.sshd_logpath),
+        (Kind::Clf, &args.clf_logpath),
+        (Kind::Json, &args.json_logpath),
+        (Kind::Traefik, &args.traefik_logpath),
+        (Kind::Haproxy, &args.haproxy_logpath),
+        (Kind::Generic, &args.generic_logpath),
+        (Kind::Logfmt, &args.logfmt_logpath),
+        (Kind::Postfix, &args.postfix_logpath),
+        (Kind::Dovecot, &args.dovecot_logpath),
+        (Kind::Postgres, &args.postgres_logpath),
+        (Kind::Mysql, &args.mysql_logpath),
+        (Kind::Vsftpd, &args.vsftpd_logpath),
+        (Kind::Proftpd, &args.proftpd_logpath),
+        (Kind::Openvpn, &args.openvpn_logpath),
+        (Kind::Netfilter, &args.netfilter_logpath),
+    ]
+    .iter()
+    .filter_map(|(kind, path)| path.as_ref().map(|p| (*kind, p.clone())))
+    .chain(
+        args.preset
+            .iter()
+            .map(|(preset, path)| (Kind::Preset(*preset), path.clone())),
+    )
+    .collect()
+}
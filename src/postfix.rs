@@ -0,0 +1,102 @@
+use anyhow::*;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{net::IpAddr, str::FromStr};
+
+use crate::timestamp;
+use crate::utils::{Offence, ParsingStatus};
+
+struct Rule {
+    matcher: String,
+    extractor: Regex,
+}
+
+lazy_static! {
+    static ref POSTFIX_BAD: [Rule; 3] = [
+        Rule {
+            matcher: "SASL ".to_string(),
+            extractor: Regex::new(r"(\[)([0-9a-fA-F:.]+)\]: SASL \S+ authentication failed")
+                .unwrap(),
+        },
+        Rule {
+            matcher: "lost connection after AUTH".to_string(),
+            extractor: Regex::new(r"(from \S*\[)([0-9a-fA-F:.]+)\]").unwrap(),
+        },
+        Rule {
+            matcher: "Relay access denied".to_string(),
+            extractor: Regex::new(r"(NOQUEUE: reject: RCPT from \S*\[)([0-9a-fA-F:.]+)\]").unwrap(),
+        },
+    ];
+}
+
+pub fn parse(line: &str) -> Result<ParsingStatus> {
+    let hits = POSTFIX_BAD
+        .iter()
+        .find(|rule| line.contains(&rule.matcher))
+        .and_then(|r| r.extractor.captures(line));
+
+    if hits.is_none() {
+        return Ok(ParsingStatus::OkEntry);
+    }
+
+    let ip = hits
+        .and_then(|c| c.get(2))
+        .and_then(|m| IpAddr::from_str(m.as_str()).ok())
+        .ok_or_else(|| anyhow!("cant parse postfix line"))?;
+
+    Ok(ParsingStatus::BadEntry(Offence::new(
+        ip,
+        timestamp::syslog(line),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positive() {
+        let vectors = [
+            "Sep 26 06:25:19 mail postfix/submission/smtpd[1942]: warning: unknown[179.124.36.195]: SASL LOGIN authentication failed: UGFzc3dvcmQ6",
+            "Sep 26 06:25:19 mail postfix/smtpd[1942]: warning: host.example.net[2001:db8::1]: SASL PLAIN authentication failed: authentication failure",
+            "Sep 26 06:25:20 mail postfix/smtpd[1942]: lost connection after AUTH from unknown[179.124.36.195]",
+            "Sep 26 06:25:21 mail postfix/smtpd[1942]: NOQUEUE: reject: RCPT from unknown[35.184.211.144]: 454 4.7.1 <spam@example.org>: Relay access denied; from=<a@example.net> to=<spam@example.org> proto=ESMTP helo=<User>",
+        ];
+
+        vectors.iter().for_each(|e| {
+            let ret = parse(e).unwrap();
+            match ret {
+                ParsingStatus::BadEntry(_) => {}
+                _ => panic!("bad parsing"),
+            }
+        })
+    }
+
+    #[test]
+    fn negative() {
+        let vectors = [
+            "Sep 26 06:25:19 mail postfix/smtpd[1942]: connect from unknown[179.124.36.195]",
+            "Sep 26 06:25:20 mail postfix/smtpd[1942]: lost connection after EHLO from unknown[179.124.36.195]",
+            "Sep 26 06:25:21 mail postfix/smtpd[1942]: 4A1B2C3D4E: client=mx.example.net[35.184.211.144], sasl_method=PLAIN, sasl_username=neal",
+        ];
+
+        vectors.iter().for_each(|e| {
+            let ret = parse(e).unwrap();
+            match ret {
+                ParsingStatus::OkEntry => {}
+                _ => panic!("bad parsing"),
+            }
+        })
+    }
+
+    #[test]
+    fn malformed() {
+        let vectors = [
+            "Sep 26 06:25:20 mail postfix/smtpd[1942]: lost connection after AUTH from unknown[179.124.36.195.232]",
+        ];
+
+        vectors.iter().for_each(|e| {
+            parse(e).expect_err("");
+        })
+    }
+}
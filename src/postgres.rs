@@ -0,0 +1,196 @@
+use anyhow::*;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{net::IpAddr, str::FromStr};
+
+use crate::timestamp;
+use crate::utils::{Offence, ParsingStatus};
+
+lazy_static! {
+    static ref RE_ESCAPE: Regex = Regex::new(r"%-?\d*([a-zA-Z%])").unwrap();
+    static ref RE_PASSWORD: Regex =
+        Regex::new(r#"password authentication failed for user "([^"]*)""#).unwrap();
+    static ref RE_HBA: Regex =
+        Regex::new(r#"no pg_hba\.conf entry for host "([^"]+)", user "([^"]*)""#).unwrap();
+}
+
+/// Regex of the server's `log_line_prefix`, to read the client host and time of a line
+pub struct Prefix(Regex);
+
+impl Prefix {
+    pub fn new(log_line_prefix: &str) -> Result<Prefix> {
+        let mut re = String::from("^");
+        let mut last = 0;
+        let mut seen = vec![];
+        for c in RE_ESCAPE.captures_iter(log_line_prefix) {
+            let m = c.get(0).unwrap();
+            re.push_str(&regex::escape(&log_line_prefix[last..m.start()]));
+            last = m.end();
+
+            // a group can only be named once
+            let group = match &c[1] {
+                "h" | "r" => "ip",
+                "m" | "t" => "time",
+                "u" => "user",
+                _ => "",
+            };
+            let first = !group.is_empty() && !seen.contains(&group);
+            seen.push(group);
+            re.push_str(match &c[1] {
+                "h" if first => r"(?P<ip>[^\s(]*)",
+                "r" if first => r"(?P<ip>[^\s(]*)(?:\(\d+\))?",
+                "m" | "t" if first => {
+                    r"(?P<time>\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2}(?:\.\d+)?)(?: (?P<tz>\S+))?"
+                }
+                "u" if first => r"(?P<user>\S*?)",
+                "%" => "%",
+                // stops there for background processes
+                "q" => "",
+                _ => r".*?",
+            });
+        }
+        re.push_str(&regex::escape(&log_line_prefix[last..]));
+
+        let re = Regex::new(&re).context("invalid postgres log_line_prefix")?;
+        Ok(Prefix(re))
+    }
+}
+
+impl Default for Prefix {
+    fn default() -> Self {
+        Prefix::new("%m [%p] ").unwrap()
+    }
+}
+
+// %m and %t are in the server's timezone, which is usually UTC, a name or an offset
+fn time(date: &str, tz: Option<&str>) -> Option<u64> {
+    let iso = date.replacen(' ', "T", 1);
+    match tz {
+        Some("UTC") | Some("GMT") => timestamp::rfc3339(&format!("{}Z", iso)),
+        Some(tz) if tz.starts_with(['+', '-']) && tz.len() >= 3 => {
+            let minutes = tz[3..].trim_start_matches(':');
+            let minutes = if minutes.is_empty() { "00" } else { minutes };
+            timestamp::rfc3339(&format!("{}{}:{}", iso, &tz[..3], minutes))
+        }
+        _ => timestamp::any(date),
+    }
+}
+
+pub fn parse(line: &str, prefix: &Prefix) -> Result<ParsingStatus> {
+    let password = RE_PASSWORD.captures(line);
+    let hba = RE_HBA.captures(line);
+    if password.is_none() && hba.is_none() {
+        return Ok(ParsingStatus::OkEntry);
+    }
+
+    let prefixed = prefix.0.captures(line);
+    let ts = prefixed
+        .as_ref()
+        .and_then(|c| c.name("time").map(|t| (t, c.name("tz"))))
+        .and_then(|(t, tz)| time(t.as_str(), tz.map(|tz| tz.as_str())));
+
+    // the rejected host is in the message, password failures only have it in the prefix
+    let (host, user) = match (&hba, &password) {
+        (Some(c), _) => (c.get(1), c.get(2)),
+        (None, Some(c)) => (prefixed.as_ref().and_then(|p| p.name("ip")), c.get(1)),
+        _ => (None, None),
+    };
+    let host = host
+        .map(|h| h.as_str())
+        .ok_or_else(|| anyhow!("cant parse postgres line - host, is %h in log_line_prefix ?"))?;
+
+    // local socket connections can't be banned
+    if host == "[local]" {
+        return Ok(ParsingStatus::OkEntry);
+    }
+
+    let ip = IpAddr::from_str(host).map_err(|_| anyhow!("cant parse postgres line - ip"))?;
+    let user = user.map(|u| u.as_str().to_string());
+
+    Ok(ParsingStatus::BadEntry(
+        Offence::new(ip, ts).with_user(user),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positive() {
+        let prefix = Prefix::new("%m [%p] %q%u@%d %h ").unwrap();
+        let vectors = [
+            r#"2021-09-26 06:25:19.123 UTC [4242] postgres@postgres 179.124.36.195 FATAL:  password authentication failed for user "postgres""#,
+            r#"2021-09-26 06:25:19.123 UTC [4242] admin@app 179.124.36.195 FATAL:  no pg_hba.conf entry for host "35.184.211.144", user "admin", database "app", SSL off"#,
+        ];
+
+        vectors.iter().for_each(|e| {
+            let ret = parse(e, &prefix).unwrap();
+            match ret {
+                ParsingStatus::BadEntry(_) => {}
+                _ => panic!("bad parsing"),
+            }
+        });
+
+        match parse(vectors[0], &prefix).unwrap() {
+            ParsingStatus::BadEntry(o) => {
+                assert_eq!(o.ip, "179.124.36.195".parse::<IpAddr>().unwrap());
+                assert_eq!(o.ts, Some(1632637519));
+                assert_eq!(o.user.as_deref(), Some("postgres"));
+            }
+            _ => panic!("bad parsing"),
+        }
+
+        // host from the message, whatever the prefix
+        match parse(vectors[1], &Prefix::default()).unwrap() {
+            ParsingStatus::BadEntry(o) => {
+                assert_eq!(o.ip, "35.184.211.144".parse::<IpAddr>().unwrap());
+                assert_eq!(o.ts, Some(1632637519));
+            }
+            _ => panic!("bad parsing"),
+        }
+
+        // remote host and port, with an offset timezone
+        let prefix = Prefix::new("%t [%p]: [%l-1] user=%u,db=%d,client=%r ").unwrap();
+        assert!(Prefix::new("%m %t %h %r %u %u ").is_ok());
+        let line = r#"2021-09-26 08:25:19 +02 [4242]: [3-1] user=neal,db=app,client=2001:db8::1(41883) FATAL:  password authentication failed for user "neal""#;
+        match parse(line, &prefix).unwrap() {
+            ParsingStatus::BadEntry(o) => {
+                assert_eq!(o.ip, "2001:db8::1".parse::<IpAddr>().unwrap());
+                assert_eq!(o.ts, Some(1632637519));
+            }
+            _ => panic!("bad parsing"),
+        }
+    }
+
+    #[test]
+    fn negative() {
+        let prefix = Prefix::new("%m [%p] %q%u@%d %h ").unwrap();
+        let vectors = [
+            r#"2021-09-26 06:25:19.123 UTC [4242] postgres@postgres 179.124.36.195 LOG:  connection authorized: user=postgres database=postgres"#,
+            r#"2021-09-26 06:25:19.123 UTC [4242] postgres@postgres [local] FATAL:  password authentication failed for user "postgres""#,
+            r#"2021-09-26 06:25:19.123 UTC [4242] LOG:  checkpoint starting: time"#,
+        ];
+
+        vectors.iter().for_each(|e| {
+            let ret = parse(e, &prefix).unwrap();
+            match ret {
+                ParsingStatus::OkEntry => {}
+                _ => panic!("bad parsing"),
+            }
+        })
+    }
+
+    #[test]
+    fn malformed() {
+        let vectors = [
+            // no host in the default prefix
+            r#"2021-09-26 06:25:19.123 UTC [4242] FATAL:  password authentication failed for user "postgres""#,
+            r#"2021-09-26 06:25:19.123 UTC [4242] FATAL:  no pg_hba.conf entry for host "db.example.net", user "admin", database "app", SSL off"#,
+        ];
+
+        vectors.iter().for_each(|e| {
+            parse(e, &Prefix::default()).expect_err("");
+        })
+    }
+}
@@ -0,0 +1,236 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::*;
+use regex::Regex;
+
+use crate::condition::parse_condition;
+use crate::generic::{self, GenericRules};
+use crate::http::HttpRules;
+use crate::json;
+use crate::logfmt::{self, LogfmtRules};
+use crate::utils::{resolve_path, ParsingStatus};
+
+/// Self-hosted apps with a well-known login failure line
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Preset {
+    Vaultwarden,
+    Gitea,
+    Nextcloud,
+    HomeAssistant,
+    Grafana,
+}
+
+pub const PRESETS: [Preset; 5] = [
+    Preset::Vaultwarden,
+    Preset::Gitea,
+    Preset::Nextcloud,
+    Preset::HomeAssistant,
+    Preset::Grafana,
+];
+
+impl FromStr for Preset {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Preset> {
+        PRESETS
+            .iter()
+            .find(|p| p.to_string() == s)
+            .copied()
+            .ok_or_else(|| anyhow!("unknown preset {:?}", s))
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Preset::Vaultwarden => "vaultwarden",
+            Preset::Gitea => "gitea",
+            Preset::Nextcloud => "nextcloud",
+            Preset::HomeAssistant => "home-assistant",
+            Preset::Grafana => "grafana",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A preset logfile, as name=path - e.g. vaultwarden=/var/log/vaultwarden.log
+pub fn parse_preset(a: &str) -> Result<(Preset, PathBuf)> {
+    let (name, path) = a
+        .split_once('=')
+        .filter(|(_, p)| !p.is_empty())
+        .ok_or_else(|| anyhow!("invalid preset {:?}, expected name=path", a))?;
+    Ok((Preset::from_str(name)?, resolve_path(path)?))
+}
+
+fn generic(ip: &str, positive: &str) -> Result<Rules> {
+    let rules = GenericRules::new(vec![Regex::new(ip)?], &[positive.to_string()], &[], &[])?;
+    Ok(Rules::Generic(rules))
+}
+
+pub enum Rules {
+    Generic(GenericRules),
+    Json(json::Layout),
+    Logfmt(LogfmtRules),
+}
+
+impl Rules {
+    pub fn new(preset: Preset) -> Result<Rules> {
+        match preset {
+            // [2021-09-26 06:25:19.123][vaultwarden::api::identity][ERROR] Username or password is incorrect. Try again. IP: 179.124.36.195. Username: neal@example.org.
+            Preset::Vaultwarden => generic(
+                r"^(?:\[(?P<time>[\d-]+ [\d:.]+)\])?.*IP: (?P<ip>[0-9a-fA-F:.]*[0-9a-fA-F])\.?(?: Username: (?P<user>\S+?)\.?$)?",
+                r"Username or password is incorrect\. Try again\.",
+            ),
+            // 2021/09/26 06:25:19 ...ers/web/auth/auth.go:123:SignInPost() [I] Failed authentication attempt for neal from 179.124.36.195:41883: user does not exist
+            Preset::Gitea => generic(
+                r"^(?:(?P<time>\d{4}/\d{2}/\d{2} \d{2}:\d{2}:\d{2}) )?.*Failed authentication attempt for (?P<user>.+?) from (?:\[(?P<ip>[0-9a-fA-F:.]+)\]|(?P<ip_1>[0-9.]+))(?::\d+)?:",
+                r"Failed authentication attempt for ",
+            ),
+            // JSON log, the message holds the user
+            Preset::Nextcloud => Ok(Rules::Json(json::Layout {
+                ip: "remoteAddr".to_string(),
+                ts: "time".to_string(),
+                conditions: vec![parse_condition("message~^Login failed")?],
+                ..json::Layout::default()
+            })),
+            // 2021-09-26 06:25:19.123 WARNING (MainThread) [homeassistant.components.http.ban] Login attempt or request with invalid authentication from scanner.example.net (179.124.36.195). Requested URL: '/auth/token'.
+            Preset::HomeAssistant => generic(
+                r"^(?:(?P<time>\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2}(?:\.\d+)?) )?.*?invalid authentication from (?:\S+ \((?P<ip>[0-9a-fA-F:.]+)\)|(?P<ip_1>[0-9a-fA-F:.]*[0-9a-fA-F]))",
+                r"Login attempt or request with invalid authentication from ",
+            ),
+            // logfmt, lvl= in older releases
+            Preset::Grafana => Ok(Rules::Logfmt(LogfmtRules::new(
+                "remote_addr",
+                "t",
+                Some("uname"),
+                &[parse_condition("msg~(?i)invalid username or password")?],
+            )?)),
+        }
+    }
+
+    pub fn parse(&self, line: &str) -> Result<ParsingStatus> {
+        match self {
+            Rules::Generic(rules) => generic::parse(line, rules),
+            Rules::Json(layout) => json::parse(line, layout, &[], &HttpRules::default(), None),
+            Rules::Logfmt(rules) => logfmt::parse(line, rules),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::IpAddr;
+
+    fn offence(preset: Preset, line: &str) -> crate::utils::Offence {
+        match Rules::new(preset).unwrap().parse(line).unwrap() {
+            ParsingStatus::BadEntry(o) => o,
+            _ => panic!("bad parsing"),
+        }
+    }
+
+    fn ok(preset: Preset, line: &str) {
+        match Rules::new(preset).unwrap().parse(line).unwrap() {
+            ParsingStatus::OkEntry => {}
+            _ => panic!("bad parsing"),
+        }
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn presets() {
+        for preset in PRESETS {
+            assert_eq!(Preset::from_str(&preset.to_string()).unwrap(), preset);
+            assert!(Rules::new(preset).is_ok());
+        }
+
+        let (preset, path) = parse_preset("gitea=-").unwrap();
+        assert_eq!(preset, Preset::Gitea);
+        assert_eq!(path, PathBuf::from("-"));
+        assert!(parse_preset("nope=/var/log/nope.log").is_err());
+        assert!(parse_preset("gitea=").is_err());
+    }
+
+    #[test]
+    fn vaultwarden() {
+        let o = offence(Preset::Vaultwarden, "[2021-09-26 06:25:19.123][vaultwarden::api::identity][ERROR] Username or password is incorrect. Try again. IP: 179.124.36.195. Username: neal@example.org.");
+        assert_eq!(o.ip, ip("179.124.36.195"));
+        assert_eq!(o.user.as_deref(), Some("neal@example.org"));
+        assert!(o.ts.is_some());
+
+        let o = offence(Preset::Vaultwarden, "[2021-09-26 06:25:19.123][vaultwarden::api::identity][ERROR] Username or password is incorrect. Try again. IP: 2001:db8::1. Username: neal.");
+        assert_eq!(o.ip, ip("2001:db8::1"));
+
+        ok(
+            Preset::Vaultwarden,
+            "[2021-09-26 06:25:19.123][request][INFO] POST /identity/connect/token",
+        );
+    }
+
+    #[test]
+    fn gitea() {
+        let o = offence(Preset::Gitea, "2021/09/26 06:25:19 ...ers/web/auth/auth.go:123:SignInPost() [I] Failed authentication attempt for neal from 179.124.36.195:41883: user does not exist [uid: 0, name: neal]");
+        assert_eq!(o.ip, ip("179.124.36.195"));
+        assert_eq!(o.user.as_deref(), Some("neal"));
+        assert!(o.ts.is_some());
+
+        let o = offence(Preset::Gitea, "2021/09/26 06:25:19 ...ers/web/auth/auth.go:123:SignInPost() [I] Failed authentication attempt for admin from [2001:db8::1]:41883: password is invalid");
+        assert_eq!(o.ip, ip("2001:db8::1"));
+
+        ok(Preset::Gitea, "2021/09/26 06:25:19 ...ers/web/auth/auth.go:123:SignInPost() [I] Successful login for neal from 179.124.36.195:41883");
+    }
+
+    #[test]
+    fn nextcloud() {
+        let o = offence(
+            Preset::Nextcloud,
+            r#"{"reqId":"YU8Qz","level":2,"time":"2021-09-26T06:25:19+00:00","remoteAddr":"179.124.36.195","user":"--","app":"core","method":"POST","url":"/login","message":"Login failed: neal (Remote IP: 179.124.36.195)","userAgent":"Mozilla/5.0","version":"22.2.0.2"}"#,
+        );
+        assert_eq!(o.ip, ip("179.124.36.195"));
+        assert_eq!(o.ts, Some(1632637519));
+
+        ok(
+            Preset::Nextcloud,
+            r#"{"reqId":"YU8Qz","level":1,"time":"2021-09-26T06:25:19+00:00","remoteAddr":"179.124.36.195","app":"files","message":"Scan done"}"#,
+        );
+    }
+
+    #[test]
+    fn home_assistant() {
+        let o = offence(Preset::HomeAssistant, "2021-09-26 06:25:19.123 WARNING (MainThread) [homeassistant.components.http.ban] Login attempt or request with invalid authentication from 179.124.36.195 (179.124.36.195). Requested URL: '/auth/token'. (Mozilla/5.0)");
+        assert_eq!(o.ip, ip("179.124.36.195"));
+        assert!(o.ts.is_some());
+
+        let o = offence(Preset::HomeAssistant, "2021-09-26 06:25:19.123 WARNING (MainThread) [homeassistant.components.http.ban] Login attempt or request with invalid authentication from a.example.net (35.184.211.144). Requested URL: '/api/'.");
+        assert_eq!(o.ip, ip("35.184.211.144"));
+
+        let o = offence(Preset::HomeAssistant, "Sep 26 06:25:19 hass hass[42]: WARNING (MainThread) [homeassistant.components.http.ban] Login attempt or request with invalid authentication from 2001:db8::1. Requested URL: '/api/'.");
+        assert_eq!(o.ip, ip("2001:db8::1"));
+
+        ok(
+            Preset::HomeAssistant,
+            "2021-09-26 06:25:19.123 INFO (MainThread) [homeassistant.setup] Setting up http",
+        );
+    }
+
+    #[test]
+    fn grafana() {
+        let o = offence(
+            Preset::Grafana,
+            r#"t=2021-09-26T06:25:19+0000 lvl=eror msg="Invalid username or password" logger=context userId=0 orgId=0 uname=admin error="invalid username or password" remote_addr=179.124.36.195"#,
+        );
+        assert_eq!(o.ip, ip("179.124.36.195"));
+        assert_eq!(o.ts, Some(1632637519));
+        assert_eq!(o.user.as_deref(), Some("admin"));
+
+        ok(
+            Preset::Grafana,
+            r#"t=2021-09-26T06:25:19+0000 lvl=info msg="Request Completed" logger=context userId=0 orgId=0 uname= method=GET path=/ status=302 remote_addr=179.124.36.195"#,
+        );
+    }
+}
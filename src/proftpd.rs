@@ -0,0 +1,91 @@
+use anyhow::*;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{net::IpAddr, str::FromStr};
+
+use crate::timestamp;
+use crate::utils::{Offence, ParsingStatus};
+
+lazy_static! {
+    // host.example.net (179.124.36.195[179.124.36.195]) - USER neal: no such user found
+    static ref RE_CLIENT: Regex = Regex::new(
+        r"\(\S*\[(?:::ffff:)?([0-9a-fA-F:.]+)\]\):?\s*-?\s*USER (\S+?)(?::| \(Login failed\):) (?:no such user|Incorrect password)"
+    )
+    .unwrap();
+}
+
+pub fn parse(line: &str) -> Result<ParsingStatus> {
+    if !line.contains("no such user") && !line.contains("Incorrect password") {
+        return Ok(ParsingStatus::OkEntry);
+    }
+
+    let c = RE_CLIENT
+        .captures(line)
+        .ok_or_else(|| anyhow!("cant parse proftpd line"))?;
+    let ip = IpAddr::from_str(&c[1]).map_err(|_| anyhow!("cant parse proftpd line - ip"))?;
+
+    Ok(ParsingStatus::BadEntry(
+        Offence::new(ip, timestamp::daemon(line)).with_user(Some(c[2].to_string())),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positive() {
+        let vectors = [
+            "Sep 26 06:25:19 ftp proftpd[4242]: ftp.example.net (179.124.36.195[179.124.36.195]) - USER neal: no such user found from 179.124.36.195 [179.124.36.195] to 10.0.0.2:21",
+            "Sep 26 06:25:19 ftp proftpd[4242]: ftp.example.net (scanner.example.net[35.184.211.144]) - USER root (Login failed): Incorrect password",
+            "2021-09-26 06:25:19,123 ftp proftpd[4242] ftp.example.net (::ffff:35.184.211.144[::ffff:35.184.211.144]): USER admin: no such user found from ::ffff:35.184.211.144 [::ffff:35.184.211.144] to ::ffff:10.0.0.2:21",
+        ];
+
+        vectors.iter().for_each(|e| {
+            let ret = parse(e).unwrap();
+            match ret {
+                ParsingStatus::BadEntry(_) => {}
+                _ => panic!("bad parsing"),
+            }
+        });
+
+        match parse(vectors[1]).unwrap() {
+            ParsingStatus::BadEntry(o) => {
+                assert_eq!(o.ip, "35.184.211.144".parse::<IpAddr>().unwrap());
+                assert_eq!(o.user.as_deref(), Some("root"));
+            }
+            _ => panic!("bad parsing"),
+        }
+        match parse(vectors[2]).unwrap() {
+            ParsingStatus::BadEntry(o) => assert!(o.ts.is_some()),
+            _ => panic!("bad parsing"),
+        }
+    }
+
+    #[test]
+    fn negative() {
+        let vectors = [
+            "Sep 26 06:25:19 ftp proftpd[4242]: ftp.example.net (179.124.36.195[179.124.36.195]) - FTP session opened.",
+            "Sep 26 06:25:19 ftp proftpd[4242]: ftp.example.net (179.124.36.195[179.124.36.195]) - USER neal: Login successful.",
+        ];
+
+        vectors.iter().for_each(|e| {
+            let ret = parse(e).unwrap();
+            match ret {
+                ParsingStatus::OkEntry => {}
+                _ => panic!("bad parsing"),
+            }
+        })
+    }
+
+    #[test]
+    fn malformed() {
+        let vectors = [
+            "Sep 26 06:25:19 ftp proftpd[4242]: ftp.example.net (179.124.36.195.232[179.124.36.195.232]) - USER neal: no such user found",
+        ];
+
+        vectors.iter().for_each(|e| {
+            parse(e).expect_err("");
+        })
+    }
+}
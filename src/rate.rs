@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use anyhow::*;
+use regex::RegexSet;
+
+// static assets are fetched in bursts by browsers, don't count them
+const STATIC_ASSETS: &str =
+    r"(?i)\.(css|js|mjs|map|png|jpe?g|gif|webp|avif|svg|ico|woff2?|ttf|otf|eot)(\?|$)";
+
+// prune full buckets past this many tracked ips
+const MAX_BUCKETS: usize = 10_000;
+
+pub struct RateLimiter {
+    limit: u32,
+    window: u32,
+    exempt: RegexSet,
+    buckets: Mutex<HashMap<IpAddr, (f64, u64)>>,
+    pruned: AtomicU64,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, window: u32, exempt: &[String]) -> Result<RateLimiter> {
+        ensure!(limit > 0, "http rate limit should be above 0");
+        ensure!(window > 0, "http rate window should be above 0");
+
+        let mut patterns: Vec<&str> = exempt.iter().map(|e| e.as_str()).collect();
+        patterns.push(STATIC_ASSETS);
+
+        Ok(RateLimiter {
+            limit,
+            window,
+            exempt: RegexSet::new(patterns).context("invalid http rate exemption regex")?,
+            buckets: Mutex::new(HashMap::new()),
+            pruned: AtomicU64::new(0),
+        })
+    }
+
+    pub fn is_exempt(&self, path: Option<&str>) -> bool {
+        path.is_some_and(|p| self.exempt.is_match(p))
+    }
+
+    // token bucket - holds up to `limit` tokens, refilled at `limit` per `window`.
+    // returns true when the ip ran out of tokens.
+    pub fn hit(&self, ip: IpAddr, now: u64) -> Result<bool> {
+        let capacity = self.limit as f64;
+        let refill = capacity / self.window as f64;
+
+        let mut locked_map = self.buckets.lock().map_err(|_| anyhow!("cant lock"))?;
+
+        // at most once per window, a flood of distinct ips would rescan the map on every hit
+        let pruned = self.pruned.load(Ordering::Relaxed);
+        if locked_map.len() > MAX_BUCKETS && now >= pruned + self.window as u64 {
+            self.pruned.store(now, Ordering::Relaxed);
+            locked_map.retain(|_, (tokens, ts)| {
+                *tokens + now.saturating_sub(*ts) as f64 * refill < capacity
+            });
+        }
+
+        let (tokens, ts) = locked_map.entry(ip).or_insert((capacity, now));
+        *tokens = (*tokens + now.saturating_sub(*ts) as f64 * refill).min(capacity);
+        *ts = now;
+
+        if *tokens < 1.0 {
+            locked_map.remove(&ip);
+            return Ok(true);
+        }
+
+        *tokens -= 1.0;
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn flood() {
+        let rate = RateLimiter::new(5, 1, &[]).unwrap();
+        let ip = IpAddr::from_str("8.8.8.8").unwrap();
+
+        for _ in 0..5 {
+            assert!(!rate.hit(ip, 100).unwrap());
+        }
+        assert!(rate.hit(ip, 100).unwrap());
+    }
+
+    #[test]
+    fn refill() {
+        let rate = RateLimiter::new(10, 10, &[]).unwrap();
+        let ip = IpAddr::from_str("8.8.8.8").unwrap();
+
+        // one request per second is within the limit
+        for now in 100..200 {
+            assert!(!rate.hit(ip, now).unwrap());
+        }
+
+        // the bucket is full again, but can't take more than 10 within the same second
+        for _ in 0..10 {
+            assert!(!rate.hit(ip, 200).unwrap());
+        }
+        assert!(rate.hit(ip, 200).unwrap());
+    }
+
+    #[test]
+    fn prune() {
+        let rate = RateLimiter::new(5, 10, &[]).unwrap();
+        let ip = |i: usize| IpAddr::from((i as u32 + 1).to_be_bytes());
+
+        for i in 0..=MAX_BUCKETS {
+            rate.hit(ip(i), 100).unwrap();
+        }
+
+        // refilled buckets are dropped, once per window
+        rate.hit(ip(MAX_BUCKETS + 1), 200).unwrap();
+        assert_eq!(rate.buckets.lock().unwrap().len(), 1);
+        for i in 0..=MAX_BUCKETS {
+            rate.hit(ip(i), 205).unwrap();
+        }
+        rate.hit(ip(MAX_BUCKETS + 1), 205).unwrap();
+        assert_eq!(rate.buckets.lock().unwrap().len(), MAX_BUCKETS + 2);
+    }
+
+    #[test]
+    fn exempt() {
+        let rate = RateLimiter::new(5, 1, &["^/api/health$".to_string()]).unwrap();
+
+        assert!(rate.is_exempt(Some("/static/app.js")));
+        assert!(rate.is_exempt(Some("/logo.PNG?v=2")));
+        assert!(rate.is_exempt(Some("/api/health")));
+        assert!(!rate.is_exempt(Some("/api/login")));
+        assert!(!rate.is_exempt(None));
+    }
+}
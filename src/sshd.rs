@@ -3,7 +3,8 @@ use lazy_static::lazy_static;
 use regex::Regex;
 use std::{net::IpAddr, str::FromStr};
 
-use crate::utils::ParsingStatus;
+use crate::timestamp;
+use crate::utils::{Offence, ParsingStatus};
 
 struct Rule {
     matcher: String,
@@ -42,10 +43,14 @@ pub fn parse(line: &str) -> Result<ParsingStatus> {
         .and_then(|m| IpAddr::from_str(m.as_str()).ok())
         .ok_or_else(|| anyhow!("cant parse sshd line"))?;
 
-    Ok(ParsingStatus::BadEntry(ip))
+    Ok(ParsingStatus::BadEntry(Offence::new(
+        ip,
+        timestamp::syslog(line),
+    )))
 }
 
 #[cfg(test)]
+#[allow(clippy::explicit_auto_deref)]
 mod tests {
     use super::*;
 
@@ -0,0 +1,404 @@
+use std::net::SocketAddr;
+use std::result::Result::Ok;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::*;
+use lazy_static::lazy_static;
+use regex::Regex;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::Semaphore;
+
+use crate::blocklist::Net;
+use crate::input::Entry;
+use crate::parsers::Kind;
+use crate::timestamp;
+use crate::utils::log;
+
+const MAX_MESSAGE: usize = 64 * 1024;
+
+// octet-counting length, with its trailing space
+const MAX_LENGTH: u64 = 8;
+
+// tcp connections held at once, past this they're closed right away
+const MAX_CONNECTIONS: usize = 128;
+
+// relays usually keep their connection open, and send at least some messages now and then
+const IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+// out of file descriptors and the likes, connections have to go away first
+const ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    static ref RE_PRI: Regex = Regex::new(r"^<(\d{1,3})>").unwrap();
+    static ref RE_RFC3164: Regex = Regex::new(
+        r"^([A-Z][a-z]{2}\s+\d{1,2}\s\d{2}:\d{2}:\d{2})\s+(?:\S*[^\s:]\s+)?([^\s\[:]+)(?:\[[^\]]*\])?:\s?(.*)$"
+    )
+    .unwrap();
+}
+
+/// Routes messages of an app-name (RFC5424) or tag (RFC3164) to a parser, e.g. sshd=sshd
+#[derive(Clone, Debug)]
+pub struct SyslogRoute {
+    pub kind: Kind,
+    pub app: String,
+}
+
+pub fn parse_route(a: &str) -> Result<SyslogRoute> {
+    let (kind, app) = a
+        .split_once('=')
+        .filter(|(_, app)| !app.is_empty())
+        .ok_or_else(|| anyhow!("invalid syslog route {:?}, expected parser=app-name", a))?;
+
+    Ok(SyslogRoute {
+        kind: Kind::from_str(kind)?,
+        app: app.to_string(),
+    })
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Message {
+    pub app: Option<String>,
+    pub ts: Option<u64>,
+    pub msg: String,
+}
+
+// space separated header field, - being nil
+fn next_field<'a>(rest: &mut &'a str) -> Option<&'a str> {
+    let (field, tail) = rest.split_once(' ').unwrap_or((rest, ""));
+    *rest = tail;
+    Some(field).filter(|f| !f.is_empty())
+}
+
+// VERSION TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA MSG
+fn parse_rfc5424(mut rest: &str) -> Option<Message> {
+    let ts = next_field(&mut rest)?;
+    let _hostname = next_field(&mut rest)?;
+    let app = next_field(&mut rest)?;
+    let _procid = next_field(&mut rest)?;
+    let _msgid = next_field(&mut rest)?;
+
+    // structured data is either nil, or [elements] where \] is escaped
+    let msg = if let Some(tail) = rest.strip_prefix('-') {
+        tail
+    } else {
+        let mut depth = 0;
+        let mut escaped = false;
+        let mut end = None;
+        for (i, c) in rest.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '[' => depth += 1,
+                ']' => depth -= 1,
+                ' ' if depth == 0 => {
+                    end = Some(i);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        end.map(|i| &rest[i..]).unwrap_or("")
+    };
+    let msg = msg.strip_prefix(' ').unwrap_or(msg);
+    let msg = msg.strip_prefix('\u{feff}').unwrap_or(msg);
+
+    Some(Message {
+        app: Some(app).filter(|a| *a != "-").map(|a| a.to_string()),
+        ts: Some(ts).filter(|t| *t != "-").and_then(timestamp::rfc3339),
+        msg: msg.to_string(),
+    })
+}
+
+fn parse_rfc3164(rest: &str) -> Message {
+    match RE_RFC3164.captures(rest) {
+        Some(c) => Message {
+            app: c.get(2).map(|a| a.as_str().to_string()),
+            ts: timestamp::syslog(rest),
+            msg: c.get(3).map(|m| m.as_str()).unwrap_or("").to_string(),
+        },
+        // best effort, relays can mangle the header
+        None => Message {
+            app: None,
+            ts: None,
+            msg: rest.to_string(),
+        },
+    }
+}
+
+pub fn parse_message(raw: &str) -> Result<Message> {
+    let raw = raw.trim_end_matches(['\r', '\n', '\0']);
+    let pri = RE_PRI
+        .find(raw)
+        .ok_or_else(|| anyhow!("cant parse syslog message - pri"))?;
+    let rest = &raw[pri.end()..];
+
+    if let Some(rest) = rest.strip_prefix("1 ") {
+        return parse_rfc5424(rest).ok_or_else(|| anyhow!("cant parse syslog message - header"));
+    }
+    Ok(parse_rfc3164(rest))
+}
+
+fn route(raw: &str, routes: &[SyslogRoute]) -> Result<Option<Entry>> {
+    let message = parse_message(raw)?;
+    let kind = routes
+        .iter()
+        .find(|r| message.app.as_deref() == Some(r.app.as_str()))
+        .map(|r| r.kind);
+
+    Ok(kind.map(|kind| Entry {
+        kind,
+        line: message.msg,
+        ts: message.ts,
+        cursor: None,
+    }))
+}
+
+/// Reads a message framed by octet-counting (RFC6587 `LEN MSG`), or by newlines
+pub async fn read_frame<R: AsyncBufRead + Unpin>(r: &mut R) -> Result<Option<String>> {
+    let first = match r.fill_buf().await?.first() {
+        Some(b) => *b,
+        None => return Ok(None),
+    };
+
+    // bounded, a peer never sending the delimiter would grow the buffer forever
+    let mut buf = vec![];
+    if first.is_ascii_digit() {
+        (&mut *r)
+            .take(MAX_LENGTH)
+            .read_until(b' ', &mut buf)
+            .await?;
+        ensure!(buf.last() == Some(&b' '), "syslog frame length too long");
+        let len = std::str::from_utf8(&buf)?
+            .trim_end()
+            .parse::<usize>()
+            .context("invalid syslog frame length")?;
+        ensure!(len <= MAX_MESSAGE, "syslog frame too long, {}", len);
+
+        buf = vec![0; len];
+        r.read_exact(&mut buf).await?;
+    } else {
+        let limit = MAX_MESSAGE as u64 + 1;
+        let read = (&mut *r).take(limit).read_until(b'\n', &mut buf).await?;
+        ensure!(
+            read < limit as usize || buf.last() == Some(&b'\n'),
+            "syslog message too long"
+        );
+    }
+
+    Ok(Some(String::from_utf8_lossy(&buf).to_string()))
+}
+
+// any peer when no allow list is given
+fn is_allowed(allow: &[Net], peer: SocketAddr) -> bool {
+    let ip = peer.ip().to_canonical();
+    allow.is_empty() || allow.iter().any(|n| n.contains(ip))
+}
+
+pub async fn listen_udp(
+    addr: SocketAddr,
+    routes: Vec<SyslogRoute>,
+    allow: Vec<Net>,
+    tx: Sender<Entry>,
+) -> Result<()> {
+    let socket = UdpSocket::bind(addr)
+        .await
+        .with_context(|| format!("cant listen on udp {}", addr))?;
+
+    let mut buf = vec![0; MAX_MESSAGE];
+    loop {
+        let (len, peer) = socket.recv_from(&mut buf).await?;
+        if !is_allowed(&allow, peer) {
+            continue;
+        }
+        let raw = String::from_utf8_lossy(&buf[..len]);
+        match route(&raw, &routes) {
+            Ok(Some(entry)) => tx.send(entry).await?,
+            Ok(None) => {}
+            Err(e) => {
+                log!("ERR: {:?}", e);
+            }
+        }
+    }
+}
+
+pub async fn listen_tcp(
+    addr: SocketAddr,
+    routes: Vec<SyslogRoute>,
+    allow: Vec<Net>,
+    tx: Sender<Entry>,
+) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("cant listen on tcp {}", addr))?;
+    let connections = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                log!("ERR: syslog tcp {} cant accept connection {:?}", addr, e);
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+        if !is_allowed(&allow, peer) {
+            log!("syslog connection from {} not allowed", peer);
+            continue;
+        }
+        let permit = match connections.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                log!(
+                    "syslog tcp connections full, closing connection from {}",
+                    peer
+                );
+                continue;
+            }
+        };
+        let routes = routes.clone();
+        let tx = tx.clone();
+
+        tokio::spawn(async move {
+            let _permit = permit;
+            let mut reader = BufReader::new(stream);
+            loop {
+                let raw = match tokio::time::timeout(IDLE_TIMEOUT, read_frame(&mut reader)).await {
+                    Ok(Ok(Some(raw))) => raw,
+                    Ok(Ok(None)) => return,
+                    Ok(Err(e)) => {
+                        log!("ERR: syslog connection from {} {:?}", peer, e);
+                        return;
+                    }
+                    Err(_) => {
+                        log!("syslog connection from {} idle, closing it", peer);
+                        return;
+                    }
+                };
+
+                match route(&raw, &routes) {
+                    Ok(Some(entry)) => {
+                        if tx.send(entry).await.is_err() {
+                            return;
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        log!("ERR: {:?}", e);
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc3164() {
+        let msg = parse_message("<38>Sep 26 06:25:19 livecompute sshd[23246]: Failed password for root from 179.124.36.195 port 41883 ssh2\n").unwrap();
+        assert_eq!(msg.app.as_deref(), Some("sshd"));
+        assert_eq!(msg.ts, timestamp::syslog("Sep 26 06:25:19 livecompute"));
+        assert_eq!(
+            msg.msg,
+            "Failed password for root from 179.124.36.195 port 41883 ssh2"
+        );
+
+        // no hostname, as sent by some local relays
+        let msg = parse_message("<38>Sep  2 06:25:19 caddy: {\"status\":200}").unwrap();
+        assert_eq!(msg.app.as_deref(), Some("caddy"));
+        assert_eq!(msg.msg, "{\"status\":200}");
+
+        assert!(parse_message("Sep 26 06:25:19 livecompute sshd[23246]: hi").is_err());
+    }
+
+    #[test]
+    fn rfc5424() {
+        let msg = parse_message("<165>1 2021-09-26T06:25:19.003Z mymachine.example.com sshd 23246 ID47 - Invalid user neal from 35.184.211.144").unwrap();
+        assert_eq!(
+            msg,
+            Message {
+                app: Some("sshd".to_string()),
+                ts: Some(1632637519),
+                msg: "Invalid user neal from 35.184.211.144".to_string(),
+            }
+        );
+
+        let msg = parse_message(r#"<165>1 - host web - - [exampleSDID@32473 iut="3" eventSource="App\]lication"][other a="b"] ﻿8.8.8.8 - p [25/Sep/2021:13:49:56 +0200] "GET / HTTP/2.0" 401 923"#).unwrap();
+        assert_eq!(msg.app.as_deref(), Some("web"));
+        assert_eq!(msg.ts, None);
+        assert_eq!(
+            msg.msg,
+            r#"8.8.8.8 - p [25/Sep/2021:13:49:56 +0200] "GET / HTTP/2.0" 401 923"#
+        );
+
+        // no message
+        let msg = parse_message("<165>1 - host - - - -").unwrap();
+        assert_eq!(msg.app, None);
+        assert_eq!(msg.msg, "");
+    }
+
+    #[test]
+    fn routing() {
+        let routes = [
+            parse_route("sshd=sshd").unwrap(),
+            parse_route("json=caddy").unwrap(),
+        ];
+        assert!(parse_route("sshd=").is_err());
+        assert!(parse_route("nope=sshd").is_err());
+
+        let entry = route("<38>Sep 26 06:25:19 host caddy: {}", &routes).unwrap();
+        assert_eq!(entry.unwrap().kind, Kind::Json);
+
+        let entry = route("<38>Sep 26 06:25:19 host cron[12]: hi", &routes).unwrap();
+        assert!(entry.is_none());
+    }
+
+    #[test]
+    fn peers() {
+        let peer = |p: &str| SocketAddr::from_str(p).unwrap();
+        assert!(is_allowed(&[], peer("179.124.36.195:514")));
+
+        let allow = [
+            Net::from_str("127.0.0.1").unwrap(),
+            Net::from_str("10.0.0.0/8").unwrap(),
+        ];
+        assert!(is_allowed(&allow, peer("10.1.2.3:514")));
+        assert!(is_allowed(&allow, peer("[::ffff:127.0.0.1]:514")));
+        assert!(!is_allowed(&allow, peer("179.124.36.195:514")));
+    }
+
+    #[tokio::test]
+    async fn framing() {
+        let mut stream: &[u8] = b"11 <38>sshd: a12 <38>sshd: bc<38>sshd: d\n<38>sshd: e";
+
+        let mut frames = vec![];
+        while let Some(frame) = read_frame(&mut stream).await.unwrap() {
+            frames.push(frame);
+        }
+        assert_eq!(
+            frames,
+            [
+                "<38>sshd: a",
+                "<38>sshd: bc",
+                "<38>sshd: d\n",
+                "<38>sshd: e"
+            ]
+        );
+
+        let mut stream: &[u8] = b"9999999 <38>sshd: a";
+        assert!(read_frame(&mut stream).await.is_err());
+
+        // delimiters never coming
+        let mut stream: &[u8] = b"99999999999999999999";
+        assert!(read_frame(&mut stream).await.is_err());
+        let long = vec![b'a'; MAX_MESSAGE * 2];
+        let mut stream: &[u8] = &long;
+        assert!(read_frame(&mut stream).await.is_err());
+    }
+}
@@ -0,0 +1,222 @@
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, TimeZone};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref RE_SYSLOG: Regex =
+        Regex::new(r"^([A-Z][a-z]{2}\s+\d{1,2}\s\d{2}:\d{2}:\d{2})\s").unwrap();
+    static ref RE_RFC3339: Regex =
+        Regex::new(r"^(\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}(\.\d+)?(Z|[+-]\d{2}:\d{2}))\s").unwrap();
+    static ref RE_CLF: Regex = Regex::new(r"\[([^\]]+)\]").unwrap();
+    static ref RE_DATETIME: Regex =
+        Regex::new(r"^(\d{4}-\d{2}-\d{2}) (\d{2}:\d{2}:\d{2})[\s,.]").unwrap();
+    static ref RE_CTIME: Regex =
+        Regex::new(r"^[A-Z][a-z]{2} ([A-Z][a-z]{2}\s+\d{1,2} \d{2}:\d{2}:\d{2} \d{4})\s").unwrap();
+}
+
+// syslog timestamps (Sep 26 06:25:19) are local time, and have no year
+fn syslog_with_now(line: &str, now: DateTime<Local>) -> Option<u64> {
+    let ts = RE_SYSLOG.captures(line)?.get(1)?.as_str();
+
+    let parse_year = |year: i32| {
+        let ndt = NaiveDateTime::parse_from_str(&format!("{} {}", year, ts), "%Y %b %e %H:%M:%S");
+        ndt.ok()
+            .and_then(|ndt| Local.from_local_datetime(&ndt).earliest())
+    };
+
+    // a date in the future is from last year, e.g. a december line read in january
+    let dt = parse_year(now.year())?;
+    if dt.timestamp() > now.timestamp() + 86400 {
+        return parse_year(now.year() - 1).map(|dt| dt.timestamp() as u64);
+    }
+    Some(dt.timestamp() as u64)
+}
+
+/// Timestamp at the start of a syslog line, either `Sep 26 06:25:19` or RFC3339
+pub fn syslog(line: &str) -> Option<u64> {
+    if let Some(ts) = RE_RFC3339.captures(line).and_then(|c| c.get(1)) {
+        return rfc3339(ts.as_str());
+    }
+    syslog_with_now(line, Local::now())
+}
+
+/// Timestamp at the start of a daemon's own logfile, syslog ones or local time
+/// `2021-09-26 06:25:19` and `Sun Sep 26 06:25:19 2021`
+pub fn daemon(line: &str) -> Option<u64> {
+    if let Some(ts) = syslog(line) {
+        return Some(ts);
+    }
+    if let Some(c) = RE_DATETIME.captures(line) {
+        return any(&format!("{} {}", &c[1], &c[2]));
+    }
+    let ts = RE_CTIME.captures(line)?.get(1)?.as_str();
+    let ndt = NaiveDateTime::parse_from_str(ts, "%b %e %H:%M:%S %Y").ok()?;
+    let dt = Local.from_local_datetime(&ndt).earliest()?;
+    Some(dt.timestamp() as u64)
+}
+
+/// Line without its leading syslog or RFC3339 timestamp
+pub fn strip(line: &str) -> &str {
+    let start = RE_RFC3339
+        .find(line)
+        .or_else(|| RE_SYSLOG.find(line))
+        .map(|m| m.end())
+        .unwrap_or(0);
+    line[start..].trim_start()
+}
+
+/// Timestamp of a Common-Log-Format line, `[25/Sep/2021:13:49:56 +0200]`
+pub fn clf(line: &str) -> Option<u64> {
+    let ts = RE_CLF.captures(line)?.get(1)?.as_str();
+    let dt = DateTime::parse_from_str(ts, "%d/%b/%Y:%H:%M:%S %z").ok()?;
+    Some(dt.timestamp() as u64)
+}
+
+/// HAProxy's accept date, `26/Sep/2021:06:25:19.123` in local time
+pub fn haproxy(ts: &str) -> Option<u64> {
+    let ndt = NaiveDateTime::parse_from_str(ts, "%d/%b/%Y:%H:%M:%S%.f").ok()?;
+    let dt = Local.from_local_datetime(&ndt).earliest()?;
+    Some(dt.timestamp() as u64)
+}
+
+pub fn rfc3339(ts: &str) -> Option<u64> {
+    let dt = DateTime::parse_from_rfc3339(ts).ok()?;
+    Some(dt.timestamp() as u64)
+}
+
+/// A standalone timestamp in any of the usual formats: RFC3339, `2021-09-26 06:25:19`
+/// or `2021/09/26 06:25:19` (local time), CLF, syslog or epoch seconds
+pub fn any(ts: &str) -> Option<u64> {
+    let ts = ts.trim();
+    if let Some(ts) = rfc3339(ts) {
+        return Some(ts);
+    }
+    if let Some(dt) = ["%Y-%m-%d %H:%M:%S%.f", "%Y/%m/%d %H:%M:%S"]
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(ts, f).ok())
+        .and_then(|ndt| Local.from_local_datetime(&ndt).earliest())
+    {
+        return Some(dt.timestamp() as u64);
+    }
+    // clf, and iso 8601 with a +hhmm offset (grafana) that rfc3339 rejects
+    if let Some(dt) = ["%d/%b/%Y:%H:%M:%S %z", "%Y-%m-%dT%H:%M:%S%.f%z"]
+        .iter()
+        .find_map(|f| DateTime::parse_from_str(ts, f).ok())
+    {
+        return Some(dt.timestamp() as u64);
+    }
+    if let Some(ts) = syslog(&format!("{} ", ts)) {
+        return Some(ts);
+    }
+    ts.parse::<f64>().ok().map(|ts| ts as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn syslog_format() {
+        let line = "Sep 26 06:25:19 livecompute sshd[23246]: Failed password for root from 179.124.36.195 port 41883 ssh2";
+        let now = Local.with_ymd_and_hms(2021, 10, 1, 0, 0, 0).unwrap();
+        let expected = Local.with_ymd_and_hms(2021, 9, 26, 6, 25, 19).unwrap();
+        assert_eq!(
+            syslog_with_now(line, now),
+            Some(expected.timestamp() as u64)
+        );
+
+        // single digit days are space padded
+        let line = "Oct  2 06:25:19 livecompute sshd[23246]: Invalid user neal from 35.184.211.144";
+        let now = Local.with_ymd_and_hms(2021, 10, 3, 0, 0, 0).unwrap();
+        let expected = Local.with_ymd_and_hms(2021, 10, 2, 6, 25, 19).unwrap();
+        assert_eq!(
+            syslog_with_now(line, now),
+            Some(expected.timestamp() as u64)
+        );
+
+        // year rollover
+        let line = "Dec 31 23:59:59 livecompute sshd[23246]: Invalid user neal from 35.184.211.144";
+        let now = Local.with_ymd_and_hms(2022, 1, 1, 0, 0, 10).unwrap();
+        let expected = Local.with_ymd_and_hms(2021, 12, 31, 23, 59, 59).unwrap();
+        assert_eq!(
+            syslog_with_now(line, now),
+            Some(expected.timestamp() as u64)
+        );
+
+        let line = "2021-09-26T06:25:19.123456+02:00 livecompute sshd[23246]: Invalid user neal from 35.184.211.144";
+        assert_eq!(syslog(line), Some(1632630319));
+
+        assert_eq!(syslog("livecompute sshd[23246]: Invalid user neal"), None);
+    }
+
+    #[test]
+    fn daemon_format() {
+        let expected = Local.with_ymd_and_hms(2021, 9, 26, 6, 25, 19).unwrap();
+        let expected = Some(expected.timestamp() as u64);
+        assert_eq!(
+            daemon("2021-09-26 06:25:19 us=123 1.2.3.4:1194 hi"),
+            expected
+        );
+        assert_eq!(
+            daemon("2021-09-26 06:25:19,123 host proftpd[1]: hi"),
+            expected
+        );
+        assert_eq!(daemon("Sun Sep 26 06:25:19 2021 [pid 42] hi"), expected);
+        assert_eq!(
+            daemon("2021-09-26T06:25:19Z host vsftpd: hi"),
+            Some(1632637519)
+        );
+        assert_eq!(daemon("[pid 42] hi"), None);
+    }
+
+    #[test]
+    fn haproxy_format() {
+        let expected = Local.with_ymd_and_hms(2021, 9, 26, 6, 25, 19).unwrap();
+        assert_eq!(
+            haproxy("26/Sep/2021:06:25:19.123"),
+            Some(expected.timestamp() as u64)
+        );
+        assert_eq!(haproxy("26/Sep/2021"), None);
+    }
+
+    #[test]
+    fn stripped() {
+        assert_eq!(
+            strip("Sep  2 06:25:19 livecompute sshd[23246]: hi"),
+            "livecompute sshd[23246]: hi"
+        );
+        assert_eq!(strip("2021-09-26T06:25:19Z host sshd: hi"), "host sshd: hi");
+        assert_eq!(strip("host sshd: hi"), "host sshd: hi");
+    }
+
+    #[test]
+    fn clf_format() {
+        let line = "8.8.8.8 - p [25/Sep/2021:13:49:56 +0200] \"POST /some/rpc HTTP/2.0\" 401 923";
+        assert_eq!(clf(line), Some(1632570596));
+
+        let line = "8.8.8.8 - p [25/Sep/2021] \"POST /some/rpc HTTP/2.0\" 401 923";
+        assert_eq!(clf(line), None);
+    }
+
+    #[test]
+    fn any_format() {
+        assert_eq!(any("2021-09-26T06:25:19Z"), Some(1632637519));
+        assert_eq!(any("25/Sep/2021:13:49:56 +0200"), Some(1632570596));
+        assert_eq!(any(" 1632637519.123 "), Some(1632637519));
+        assert_eq!(any("2021-09-26T06:25:19+0000"), Some(1632637519));
+        assert_eq!(any("2021-09-26T08:25:19.042+0200"), Some(1632637519));
+
+        let expected = Local.with_ymd_and_hms(2021, 9, 26, 6, 25, 19).unwrap();
+        assert_eq!(
+            any("2021-09-26 06:25:19"),
+            Some(expected.timestamp() as u64)
+        );
+        assert_eq!(
+            any("2021/09/26 06:25:19"),
+            Some(expected.timestamp() as u64)
+        );
+        assert!(any("Sep 26 06:25:19").is_some());
+
+        assert_eq!(any("yesterday"), None);
+    }
+}
@@ -0,0 +1,116 @@
+use crate::http::{self, HttpRequest, HttpRules};
+use crate::timestamp;
+use crate::utils::ParsingStatus;
+use anyhow::*;
+use std::{net::IpAddr, str::FromStr};
+
+// traefik's JSON access log, its common format is read by the clf parser
+pub fn request(line: &str) -> Result<HttpRequest> {
+    let json: serde_json::Value = serde_json::from_str(line)?;
+
+    let client_host = json
+        .get("ClientHost")
+        .and_then(|r| r.as_str())
+        .and_then(|r| IpAddr::from_str(r).ok())
+        .ok_or_else(|| anyhow!("cant parse traefik line - ClientHost"))?;
+
+    let status = json
+        .get("DownstreamStatus")
+        .and_then(|r| r.as_u64())
+        .ok_or_else(|| anyhow!("cant parse traefik line - DownstreamStatus"))?;
+
+    let path = json
+        .get("RequestPath")
+        .and_then(|r| r.as_str())
+        .map(|r| r.to_string());
+
+    // only logged when the header is kept, accessLog.fields.headers
+    let user_agent = json
+        .get("request_User-Agent")
+        .and_then(|r| r.as_str())
+        .map(|r| r.to_string());
+
+    let ts = json
+        .get("StartUTC")
+        .or_else(|| json.get("time"))
+        .and_then(|r| r.as_str())
+        .and_then(timestamp::rfc3339);
+
+    Ok(HttpRequest {
+        ip: client_host,
+        path,
+        user_agent,
+        status: status as u32,
+        ts,
+    })
+}
+
+/// `source_ts` is the time given by the source, if the line has none
+pub fn parse(
+    line: &str,
+    valid_statuses: &[u32],
+    rules: &HttpRules,
+    source_ts: Option<u64>,
+) -> Result<ParsingStatus> {
+    let mut req = request(line)?;
+    req.ts = req.ts.or(source_ts);
+    http::assess(&req, valid_statuses, rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positive() {
+        let vectors = [
+            r#"{"ClientAddr":"179.124.36.195:41883","ClientHost":"179.124.36.195","ClientPort":"41883","DownstreamContentSize":19,"DownstreamStatus":401,"Duration":140283,"RequestAddr":"app.example.net","RequestHost":"app.example.net","RequestMethod":"POST","RequestPath":"/login","RequestProtocol":"HTTP/2.0","RouterName":"app@docker","StartUTC":"2021-09-26T06:25:19.123456789Z","level":"info","msg":"","time":"2021-09-26T06:25:19Z"}"#,
+            r#"{"ClientHost":"2001:db8::1","DownstreamStatus":403,"RequestPath":"/","request_User-Agent":"Mozilla/5.0","time":"2021-09-26T06:25:19Z"}"#,
+        ];
+
+        vectors.iter().for_each(|e| {
+            let ret = parse(e, &[200, 404], &HttpRules::default(), None).unwrap();
+            match ret {
+                ParsingStatus::BadEntry(_) => {}
+                _ => panic!("bad parsing"),
+            }
+        });
+
+        let req = request(vectors[0]).unwrap();
+        assert_eq!(req.path.as_deref(), Some("/login"));
+        assert_eq!(req.ts, Some(1632637519));
+        assert_eq!(
+            request(vectors[1]).unwrap().user_agent.as_deref(),
+            Some("Mozilla/5.0")
+        );
+    }
+
+    #[test]
+    fn negative() {
+        let vectors = [
+            r#"{"ClientHost":"179.124.36.195","DownstreamStatus":200,"RequestPath":"/","StartUTC":"2021-09-26T06:25:19Z"}"#,
+            r#"{"ClientHost":"179.124.36.195","DownstreamStatus":404,"RequestPath":"/favicon.ico","StartUTC":"2021-09-26T06:25:19Z"}"#,
+        ];
+
+        vectors.iter().for_each(|e| {
+            let ret = parse(e, &[200, 404], &HttpRules::default(), None).unwrap();
+            match ret {
+                ParsingStatus::OkEntry => {}
+                _ => panic!("bad parsing"),
+            }
+        })
+    }
+
+    #[test]
+    fn malformed() {
+        let vectors = [
+            r#"{"ClientHost":"179.124.36.195.232","DownstreamStatus":401}"#,
+            r#"{"ClientHost":"179.124.36.195"}"#,
+            r#"179.124.36.195 - - [26/Sep/2021:06:25:19 +0000] "GET / HTTP/1.1" 401 19"#,
+        ];
+
+        vectors.iter().for_each(|e| {
+            parse(e, &[200, 404], &HttpRules::default(), None).expect_err("");
+        })
+    }
+}
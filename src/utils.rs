@@ -1,15 +1,84 @@
+use crate::blocklist::Net;
+use crate::condition::{parse_condition, Condition};
+use crate::dns::parse_resolver;
+use crate::docker::{parse_source, DockerSource};
+use crate::geoip::{parse_area, Area, Geo};
+use crate::honeypot::parse_honeypot;
+use crate::journal::{parse_match, JournalMatch};
+use crate::preset::{parse_preset, Preset};
+use crate::syslog::{parse_route, SyslogRoute};
 use anyhow::{anyhow, Context, Result};
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use regex::Regex;
 use std::{
-    net::IpAddr,
-    path::{Path, PathBuf},
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
 };
 
+#[derive(Debug)]
+pub struct Offence {
+    pub ip: IpAddr,
+    pub ts: Option<u64>,
+    pub user: Option<String>,
+    pub geo: Option<Geo>,
+}
+
+impl Offence {
+    pub fn new(ip: IpAddr, ts: Option<u64>) -> Offence {
+        Offence {
+            ip,
+            ts,
+            user: None,
+            geo: None,
+        }
+    }
+
+    pub fn with_user(mut self, user: Option<String>) -> Offence {
+        self.user = user;
+        self
+    }
+
+    pub fn with_geo(mut self, geo: Option<Geo>) -> Offence {
+        self.geo = geo;
+        self
+    }
+}
+
+impl std::fmt::Display for Offence {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.ip)?;
+        if let Some(user) = &self.user {
+            write!(f, " (user {})", user)?;
+        }
+        match &self.geo {
+            Some(geo) if *geo != Geo::default() => write!(f, " [{}]", geo),
+            _ => std::fmt::Result::Ok(()),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum ParsingStatus {
     OkEntry,
-    BadEntry(IpAddr),
+    BadEntry(Offence),
+    InstantBan(Offence),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum TimeSource {
+    /// time the line was read
+    Ingest,
+    /// time logged on the line, falls back to ingest time if missing
+    Event,
+}
+
+impl TimeSource {
+    pub fn pick(&self, event: Option<u64>) -> u64 {
+        match (self, event) {
+            (TimeSource::Event, Some(ts)) => ts,
+            _ => get_epoch(),
+        }
+    }
 }
 
 pub fn get_epoch() -> u64 {
@@ -30,11 +99,11 @@ macro_rules! log{
 }
 
 pub fn resolve_path(a: &str) -> Result<PathBuf> {
-    let p = Path::new(a);
-    if !p.exists() {
-        return Err(anyhow!("path {:?} does not exist", p));
+    if a == crate::input::STDIN {
+        return Ok(PathBuf::from(a));
     }
-    let p = std::fs::canonicalize(p)?;
+    // not canonicalized nor required to exist, files come and go with rotations
+    let p = std::path::absolute(a)?;
     Ok(p)
 }
 
@@ -70,10 +139,49 @@ pub fn parse_statuses(a: &str) -> Result<Vec<u32>> {
     Ok(statuses)
 }
 
+// seconds, or with a s/m/h/d unit
+pub fn parse_duration(a: &str) -> Result<u64> {
+    let (n, unit) = match a.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => a.split_at(i),
+        None => (a, "s"),
+    };
+    let n = n.parse::<u64>().context("invalid duration")?;
+    let mult = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return Err(anyhow!("invalid duration unit {:?}, use s/m/h/d", unit)),
+    };
+    Ok(n * mult)
+}
+
 pub(crate) use log;
 
+/// Scratch directory for tests, unique per test name and process, removed on drop
+#[cfg(test)]
+pub struct TempDir(pub PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let dir = std::env::temp_dir().join(format!("blockfast-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
 #[derive(Parser, Debug)]
 #[command(
+    args_conflicts_with_subcommands = true,
     name = "Blockfast",
     author = "pierre dubouilh <pldubouilh@gmail.com>",
     arg_required_else_help = true,
@@ -84,17 +192,61 @@ Blockfast - block internets scanners fast 🍶
 Author: pierre dubouilh <pldubouilh@gmail.com>
 
 Blockfast reads logs from various sources and blocks the offending IPs using iptables and ipset.
-It supports logs from sshd, Common-Log-Format (Apache, etc..), JSON (Caddy) and a generic logs parser.
+It supports logs from sshd, postfix, dovecot, PostgreSQL, MySQL, vsftpd, proftpd, OpenVPN, netfilter (port scans), Common-Log-Format (Apache, etc..), JSON (Caddy), Traefik, HAProxy, logfmt and a generic logs parser, plus presets for Vaultwarden, Gitea, Nextcloud, Home Assistant and Grafana.
 
 Example:
     # block invalid sshd attempts & invalid http statuses from caddy
     ./blockfast -s=/var/log/auth.log -j=/caddy/logs
 
-    # generic log parser example with a log text to flag, and a regex to parse the offending IP.
-    ./blockfast --generic-logpath=/tmp/generictest --generic-positive='Failed password' --generic-ip='from ([0-9a-fA-F:.]+) port'",
+    # generic log parser example with a log regex to flag, a text to let through, and a regex to parse the offending IP.
+    ./blockfast --generic-logpath=/tmp/generictest --generic-positive-regex='Failed (password|publickey)' --generic-negative='for backup from' --generic-ip='from ([0-9a-fA-F:.]+) port'
+
+    # generic log parser running an existing fail2ban filter
+    ./blockfast --generic-logpath=/var/log/auth.log --generic-fail2ban-filter=/etc/fail2ban/filter.d/sshd.conf
+
+    # JSON logs of any app, with the ip and what makes a line bad
+    ./blockfast -j=/var/log/app.json --json-ip-path=client.address --json-match=event.outcome=failure
+
+    # logfmt logs, e.g. from Go services
+    ./blockfast --logfmt-logpath=/var/log/app.log --logfmt-match=level=warn --logfmt-match='msg~login failed'
+
+    # port scanners, from the kernel log of dropped packets
+    ./blockfast --netfilter-logpath=/var/log/kern.log --netfilter-ports=5
+
+    # honeypot ports, connecting to them gets banned right away
+    ./blockfast --honeypot=23 --honeypot=3389 --honeypot-instant-ban --honeypot-tarpit
+
+    # static blocklists on top of the jail, the private ranges FireHOL level1 lists are skipped
+    ./blockfast -s=/var/log/auth.log --blocklist=/etc/blocklists/drop.txt --blocklist=/etc/blocklists/firehol_level1.netset
+
+    # offences annotated with their country and ASN, one strike for some, more leeway for home ISPs
+    ./blockfast -s=/var/log/auth.log --geoip-db=/var/lib/GeoIP/GeoLite2-Country.mmdb --geoip-db=/var/lib/GeoIP/GeoLite2-ASN.mmdb --geoip-strict=CN --geoip-lenient=AS3320
+
+    # http logs, verified Googlebot and Bingbot crawlers are never banned
+    ./blockfast -c=/var/log/nginx/access.log --http-allow-rdns=googlebot.com --http-allow-rdns=search.msn.com --resolver=1.1.1.1
+
+    # self-hosted apps presets
+    ./blockfast --preset=vaultwarden=/var/log/vaultwarden.log --preset=gitea=/var/lib/gitea/log/gitea.log
+
+    # report what would have been banned from existing logs, without banning anything
+    ./blockfast analyze -s=/var/log/auth.log -c=/var/log/nginx/access.log",
     verbatim_doc_comment,
 )]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
 
+    #[command(flatten)]
+    pub args: Args,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// replay existing logs, rotated ones included, and report what would have been banned
+    Analyze(Args),
+}
+
+#[derive(clap::Args, Debug)]
 pub struct Args {
     /// jail time (seconds)
     #[clap(long, default_value = "21600")]
@@ -104,39 +256,267 @@ pub struct Args {
     #[clap(long, default_value = "5")]
     pub allowance: u8,
 
+    /// time used to window offences. With event time, lines older than the jail time are ignored
+    #[clap(long, value_enum, default_value = "ingest")]
+    pub time_source: TimeSource,
+
+    /// on startup, read back this far into the log files, rotated ones included, and the journal without a saved cursor (e.g. 30m, 2h), to count offences logged while not running. Docker and syslog sources start from now
+    #[clap(long, value_parser = parse_duration)]
+    pub catch_up: Option<u64>,
+
+    /// blocklist file of ips and cidrs (Spamhaus DROP, FireHOL, etc..) to block permanently, re-read on change. Private, CGNAT, loopback and link-local ranges are skipped. Can be repeated
+    #[clap(long, value_parser = resolve_path)]
+    pub blocklist: Vec<PathBuf>,
+
+    /// re-read the blocklist files this often even if unchanged (e.g. 30m, 2h)
+    #[clap(long, value_parser = parse_duration, default_value = "1h")]
+    pub blocklist_refresh: u64,
+
+    /// local MaxMind or DB-IP database (.mmdb), country or ASN, to annotate offences and apply the geoip allowances. Can be repeated
+    #[clap(long, value_parser = resolve_path)]
+    pub geoip_db: Vec<PathBuf>,
+
+    /// country code or ASN without legit users (e.g. CN or AS4134), its ips get the strict allowance. Can be repeated
+    #[clap(long, value_parser = parse_area)]
+    pub geoip_strict: Vec<Area>,
+
+    /// how many offences allowed for ips from strict countries and ASNs
+    #[clap(long, default_value = "1")]
+    pub geoip_strict_allowance: u8,
+
+    /// country code or ASN of most users (e.g. DE or AS3320), its ips get the lenient allowance, instant bans included. Wins over strict ones. Can be repeated
+    #[clap(long, value_parser = parse_area)]
+    pub geoip_lenient: Vec<Area>,
+
+    /// how many offences allowed for ips from lenient countries and ASNs (max 255)
+    #[clap(long, default_value = "20")]
+    pub geoip_lenient_allowance: u8,
+
     /// log all offences
     #[clap(short, long)]
     pub verbose: bool,
 
-    /// path of sshd logfile
+    /// path of sshd logfile or named pipe, - for stdin
     #[clap(short, long, value_parser = resolve_path)]
     pub sshd_logpath: Option<PathBuf>,
 
-    /// path of Common-Log-Format logfile (Apache, etc..)
+    /// path of Common-Log-Format logfile or named pipe (Apache, etc..), - for stdin
     #[clap(short, long, value_parser = resolve_path)]
     pub clf_logpath: Option<PathBuf>,
 
-    /// path of JSON logfile (works with Caddy)
+    /// path of JSON logfile or named pipe (works with Caddy), - for stdin
     #[clap(short, long, value_parser = resolve_path)]
     pub json_logpath: Option<PathBuf>,
 
-    /// generic parser log file path
+    /// JSON parser ip field, as a dotted path. Also accepts ip:port values
+    #[clap(long, default_value = "request.remote_ip")]
+    pub json_ip_path: String,
+
+    /// JSON parser http status field, as a dotted path
+    #[clap(long, default_value = "status")]
+    pub json_status_path: String,
+
+    /// JSON parser time field, as a dotted path. Unix time or a date
+    #[clap(long, default_value = "ts")]
+    pub json_time_path: String,
+
+    /// JSON parser condition, as field=value, field~regex or field=min..max. Lines matching all of them are bad, instead of checking the http status, the http path, user-agent and rate rules still apply. Can be repeated
+    #[clap(long, value_parser = parse_condition)]
+    pub json_match: Vec<Condition>,
+
+    /// path of Traefik JSON access logfile or named pipe, - for stdin. Its common format works with the CLF parser
+    #[clap(long, value_parser = resolve_path)]
+    pub traefik_logpath: Option<PathBuf>,
+
+    /// path of HAProxy HTTP logfile or named pipe (option httplog), - for stdin
+    #[clap(long, value_parser = resolve_path)]
+    pub haproxy_logpath: Option<PathBuf>,
+
+    /// path of postfix logfile or named pipe, - for stdin
+    #[clap(long, value_parser = resolve_path)]
+    pub postfix_logpath: Option<PathBuf>,
+
+    /// path of dovecot logfile or named pipe, - for stdin
+    #[clap(long, value_parser = resolve_path)]
+    pub dovecot_logpath: Option<PathBuf>,
+
+    /// path of PostgreSQL logfile or named pipe, - for stdin
+    #[clap(long, value_parser = resolve_path)]
+    pub postgres_logpath: Option<PathBuf>,
+
+    /// log_line_prefix of the PostgreSQL server, needs %h or %r for password failures to have a host
+    #[clap(long, default_value = "%m [%p] ")]
+    pub postgres_log_prefix: String,
+
+    /// path of MySQL or MariaDB error logfile or named pipe, - for stdin
+    #[clap(long, value_parser = resolve_path)]
+    pub mysql_logpath: Option<PathBuf>,
+
+    /// path of vsftpd logfile or named pipe, - for stdin
+    #[clap(long, value_parser = resolve_path)]
+    pub vsftpd_logpath: Option<PathBuf>,
+
+    /// path of proftpd logfile or named pipe, - for stdin
+    #[clap(long, value_parser = resolve_path)]
+    pub proftpd_logpath: Option<PathBuf>,
+
+    /// path of OpenVPN logfile or named pipe, - for stdin
+    #[clap(long, value_parser = resolve_path)]
+    pub openvpn_logpath: Option<PathBuf>,
+
+    /// path of the kernel logfile or named pipe with netfilter LOG/NFLOG lines of dropped packets, - for stdin
+    #[clap(long, value_parser = resolve_path)]
+    pub netfilter_logpath: Option<PathBuf>,
+
+    /// netfilter parser, how many distinct closed ports an ip can probe within the window before being banned
+    #[clap(long, default_value = "10")]
+    pub netfilter_ports: u32,
+
+    /// netfilter parser port scan window (seconds)
+    #[clap(long, default_value = "60")]
+    pub netfilter_window: u32,
+
+    /// netfilter parser, count udp packets as probes too. Off by default, as udp sources are easily spoofed
+    #[clap(long)]
+    pub netfilter_udp: bool,
+
+    /// systemd journal source, as parser=FIELD=value - e.g. sshd=_SYSTEMD_UNIT=ssh.service or sshd=SYSLOG_IDENTIFIER=sshd. Can be repeated
+    #[clap(long, value_parser = parse_match)]
+    pub journal: Vec<JournalMatch>,
+
+    /// file keeping the journal position, to resume from it on restart
+    #[clap(long)]
+    pub journal_cursor: Option<PathBuf>,
+
+    /// listen for syslog messages over udp on this address, e.g. 127.0.0.1:514. Anyone reaching it can get ips banned, bind to localhost or see --syslog-allow
+    #[clap(long)]
+    pub syslog_udp: Option<SocketAddr>,
+
+    /// listen for syslog messages over tcp on this address, e.g. 127.0.0.1:514. Anyone reaching it can get ips banned, bind to localhost or see --syslog-allow
+    #[clap(long)]
+    pub syslog_tcp: Option<SocketAddr>,
+
+    /// ip or cidr allowed to send syslog messages, e.g. 10.0.0.0/8. Any peer if unset. Can be repeated
+    #[clap(long)]
+    pub syslog_allow: Vec<Net>,
+
+    /// honeypot port, or ip:port, nothing legit connects to - e.g. 23, 3389, 5900 or 2323. Any connection is an offence. Can be repeated
+    #[clap(long, value_parser = parse_honeypot)]
+    pub honeypot: Vec<SocketAddr>,
+
+    /// ban ips connecting to a honeypot port right away
+    #[clap(long)]
+    pub honeypot_instant_ban: bool,
+
+    /// keep honeypot connections open, slowly sending them bytes
+    #[clap(long)]
+    pub honeypot_tarpit: bool,
+
+    /// parse syslog messages of an app-name or tag, as parser=app-name - e.g. sshd=sshd or json=caddy. Can be repeated
+    #[clap(long, value_parser = parse_route)]
+    pub syslog_route: Vec<SyslogRoute>,
+
+    /// docker container source, as parser=name or parser=label:key[=value] - e.g. json=caddy. Can be repeated
+    #[clap(long, value_parser = parse_source)]
+    pub docker: Vec<DockerSource>,
+
+    /// docker containers directory
+    #[clap(long, default_value = "/var/lib/docker/containers")]
+    pub docker_root: PathBuf,
+
+    /// generic parser log file or named pipe path, - for stdin
     #[clap(long, value_parser = resolve_path)]
     pub generic_logpath: Option<PathBuf>,
 
-    /// generic parser ip regex
+    /// generic parser ip regex - the ip (or hostname) is its `ip` named group, or else its first group. Optional `user` and `time` named groups
     #[clap(long , value_parser = parse_regex)]
     pub generic_ip: Option<Regex>,
 
-    /// generic parser positive - if a logline contains this, it is considered bad, the rest is good
+    /// generic parser positive - if a logline contains this, it is considered bad, the rest is good. Can be repeated
     #[clap(long)]
-    pub generic_positive: Option<String>,
+    pub generic_positive: Vec<String>,
+
+    /// generic parser negative - if a logline contains this, it is considered good, even if it matches a positive. Can be repeated
+    #[clap(long)]
+    pub generic_negative: Vec<String>,
+
+    /// generic parser positive regex, like --generic-positive. Can be repeated
+    #[clap(long)]
+    pub generic_positive_regex: Vec<String>,
+
+    /// generic parser negative regex, like --generic-negative. Can be repeated
+    #[clap(long)]
+    pub generic_negative_regex: Vec<String>,
+
+    /// fail2ban filter.d file, its failregex and ignoreregex are run by the generic parser on lines without their leading timestamp. Can be repeated
+    #[clap(long)]
+    pub generic_fail2ban_filter: Vec<PathBuf>,
+
+    /// logfmt parser log file or named pipe path, - for stdin
+    #[clap(long, value_parser = resolve_path)]
+    pub logfmt_logpath: Option<PathBuf>,
+
+    /// logfmt parser ip key. Also accepts ip:port values
+    #[clap(long, default_value = "remote_addr")]
+    pub logfmt_ip_key: String,
+
+    /// logfmt parser time key
+    #[clap(long, default_value = "ts")]
+    pub logfmt_time_key: String,
+
+    /// logfmt parser user key, recorded with the offences
+    #[clap(long)]
+    pub logfmt_user_key: Option<String>,
+
+    /// logfmt parser condition, as key=value, key~regex or key=min..max. Lines matching all of them are bad. Can be repeated
+    #[clap(long, value_parser = parse_condition)]
+    pub logfmt_match: Vec<Condition>,
 
-    /// generic parser negative - if a logline contains this, it is considered good, the rest is bad
+    /// self-hosted app log file, as name=path with name one of vaultwarden, gitea, nextcloud, home-assistant or grafana. Can be repeated
+    #[clap(long, value_parser = parse_preset)]
+    pub preset: Vec<(Preset, PathBuf)>,
+
+    /// hosts file to resolve hostnames logged instead of ips, e.g. /etc/hosts
     #[clap(long)]
-    pub generic_negative: Option<String>,
+    pub hosts_file: Option<PathBuf>,
+
+    /// dns server to resolve hostnames logged instead of ips, and to verify crawlers, e.g. 1.1.1.1 or 127.0.0.53:53. When watching, names are resolved in the background and their lines parsed again a few seconds later
+    #[clap(long, value_parser = parse_resolver)]
+    pub resolver: Option<SocketAddr>,
 
     /// valid http statuses (for CLF and JSON logs). Coma separated list, accepts ranges with XX.
     #[clap(long, default_value = "10x,20x,30x,404,408")]
     pub valid_http_statuses: String,
+
+    /// flag requests to well known scanner paths (/wp-login.php, /.env...) and user-agents (zgrab, masscan...), regardless of the status
+    #[clap(long)]
+    pub http_scanner_rules: bool,
+
+    /// http path regex - requests matching it are considered bad, regardless of the status. Can be repeated
+    #[clap(long)]
+    pub http_bad_path: Vec<String>,
+
+    /// http user-agent regex - requests matching it are considered bad, regardless of the status. Can be repeated
+    #[clap(long)]
+    pub http_bad_user_agent: Vec<String>,
+
+    /// ban on the first request flagged by the http path and user-agent rules
+    #[clap(long)]
+    pub http_instant_ban: bool,
+
+    /// max http requests allowed per ip within the rate window, above which it's banned. Disabled by default
+    #[clap(long)]
+    pub http_rate_limit: Option<u32>,
+
+    /// http rate window (seconds)
+    #[clap(long, default_value = "10")]
+    pub http_rate_window: u32,
+
+    /// http path regex exempted from the rate limit, on top of static assets. Can be repeated
+    #[clap(long)]
+    pub http_rate_exempt: Vec<String>,
+
+    /// domain of crawlers never banned on http logs, e.g. googlebot.com or search.msn.com. The reverse dns of the ip has to be in it, and resolve back to the ip, see --resolver. Can be repeated
+    #[clap(long)]
+    pub http_allow_rdns: Vec<String>,
 }
@@ -0,0 +1,85 @@
+use anyhow::*;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{net::IpAddr, str::FromStr};
+
+use crate::timestamp;
+use crate::utils::{Offence, ParsingStatus};
+
+lazy_static! {
+    static ref RE_FAIL: Regex =
+        Regex::new(r#"\[([^\]]*)\] FAIL LOGIN: Client "(?:::ffff:)?([0-9a-fA-F:.]+)""#).unwrap();
+}
+
+pub fn parse(line: &str) -> Result<ParsingStatus> {
+    if !line.contains("FAIL LOGIN: Client") {
+        return Ok(ParsingStatus::OkEntry);
+    }
+
+    let c = RE_FAIL
+        .captures(line)
+        .ok_or_else(|| anyhow!("cant parse vsftpd line"))?;
+    let ip = IpAddr::from_str(&c[2]).map_err(|_| anyhow!("cant parse vsftpd line - ip"))?;
+
+    Ok(ParsingStatus::BadEntry(
+        Offence::new(ip, timestamp::daemon(line)).with_user(Some(c[1].to_string())),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positive() {
+        let vectors = [
+            r#"Sun Sep 26 06:25:19 2021 [pid 4242] [neal] FAIL LOGIN: Client "179.124.36.195""#,
+            r#"Sun Sep 26 06:25:19 2021 [pid 4242] [anonymous] FAIL LOGIN: Client "::ffff:35.184.211.144", "Anonymous access is disabled.""#,
+            r#"Sep 26 06:25:19 ftp vsftpd[4242]: [root] FAIL LOGIN: Client "2001:db8::1""#,
+        ];
+
+        vectors.iter().for_each(|e| {
+            let ret = parse(e).unwrap();
+            match ret {
+                ParsingStatus::BadEntry(_) => {}
+                _ => panic!("bad parsing"),
+            }
+        });
+
+        match parse(vectors[1]).unwrap() {
+            ParsingStatus::BadEntry(o) => {
+                assert_eq!(o.ip, "35.184.211.144".parse::<IpAddr>().unwrap());
+                assert_eq!(o.user.as_deref(), Some("anonymous"));
+                assert!(o.ts.is_some());
+            }
+            _ => panic!("bad parsing"),
+        }
+    }
+
+    #[test]
+    fn negative() {
+        let vectors = [
+            r#"Sun Sep 26 06:25:19 2021 [pid 4242] CONNECT: Client "179.124.36.195""#,
+            r#"Sun Sep 26 06:25:19 2021 [pid 4242] [neal] OK LOGIN: Client "179.124.36.195""#,
+        ];
+
+        vectors.iter().for_each(|e| {
+            let ret = parse(e).unwrap();
+            match ret {
+                ParsingStatus::OkEntry => {}
+                _ => panic!("bad parsing"),
+            }
+        })
+    }
+
+    #[test]
+    fn malformed() {
+        let vectors = [
+            r#"Sun Sep 26 06:25:19 2021 [pid 4242] [neal] FAIL LOGIN: Client "179.124.36.195.232""#,
+        ];
+
+        vectors.iter().for_each(|e| {
+            parse(e).expect_err("");
+        })
+    }
+}